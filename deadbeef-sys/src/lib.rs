@@ -12,6 +12,9 @@ use thiserror::Error;
 
 static mut DEADBEEF: Option<DeadBeef> = None;
 
+/// The main playlist iterator, `PL_MAIN` isn't part of the bindings.
+const PL_MAIN: i32 = 0;
+
 #[allow(deref_nullptr)]
 mod api {
     include!(concat!(env!("OUT_DIR"), "/bindings.rs"));
@@ -131,6 +134,32 @@ impl DeadBeef {
             .into_owned();
     }
 
+    pub fn conf_get_int(item: impl AsRef<str>, default: i32) -> i32 {
+        let deadbeef = unsafe { DeadBeef::deadbeef() };
+
+        let item = LossyCString::new(item);
+        let conf_get_int = deadbeef.get().conf_get_int.unwrap();
+
+        unsafe { conf_get_int(item.as_ptr(), default) }
+    }
+
+    pub fn conf_set_str(item: impl AsRef<str>, value: impl AsRef<str>) {
+        let deadbeef = unsafe { DeadBeef::deadbeef() };
+
+        let item = LossyCString::new(item);
+        let value = LossyCString::new(value);
+        let conf_set_str = deadbeef.get().conf_set_str.unwrap();
+
+        unsafe { conf_set_str(item.as_ptr(), value.as_ptr()) }
+    }
+
+    pub fn conf_save() -> i32 {
+        let deadbeef = unsafe { DeadBeef::deadbeef() };
+        let conf_save = deadbeef.get().conf_save.unwrap();
+
+        unsafe { conf_save() }
+    }
+
     pub fn plugins() -> PluginIter {
         let deadbeef = unsafe { DeadBeef::deadbeef() };
         let plug_get_list = deadbeef.get().plug_get_list.unwrap();
@@ -156,12 +185,122 @@ impl DeadBeef {
         None
     }
 
-    pub fn call_action_by_name(name: &str) {
+    pub fn call_action_by_name(name: &str, context: ddb_action_context_t) {
         if let Some(action) = Self::find_action_by_name(name) {
-            action.call(DDB_ACTION_CTX_MAIN);
+            Self::call_action(&action, context);
+        }
+    }
+
+    /// Calls an action the way the UI does, pointing the action playlist at
+    /// the current or now playing playlist for the contexts that need one.
+    pub fn call_action(action: &plugin::Action, context: ddb_action_context_t) {
+        let plt = match context {
+            DDB_ACTION_CTX_PLAYLIST => Playlist::current(),
+            DDB_ACTION_CTX_NOWPLAYING => Self::streamer_get_playing_track()
+                .and_then(|it| it.playlist())
+                .or_else(Playlist::current),
+            _ => None,
+        };
+
+        if let Some(plt) = &plt {
+            Self::action_set_playlist(Some(plt));
+        }
+        action.call(context);
+        if plt.is_some() {
+            Self::action_set_playlist(None);
+        }
+    }
+
+    pub fn action_set_playlist(plt: Option<&Playlist>) {
+        let deadbeef = unsafe { DeadBeef::deadbeef() };
+        let action_set_playlist = deadbeef.get().action_set_playlist.unwrap();
+
+        unsafe {
+            action_set_playlist(plt.map_or(ptr::null_mut(), |p| p.as_ptr()));
         }
     }
 
+    pub fn streamer_get_playing_track() -> Option<PlItem> {
+        let deadbeef = unsafe { DeadBeef::deadbeef() };
+        let streamer_get_playing_track = deadbeef.get().streamer_get_playing_track_safe.unwrap();
+
+        PlItem::from_raw(unsafe { streamer_get_playing_track() }).ok()
+    }
+
+    /// Position in the playing track, in seconds.
+    pub fn streamer_get_playpos() -> f32 {
+        let deadbeef = unsafe { DeadBeef::deadbeef() };
+        let streamer_get_playpos = deadbeef.get().streamer_get_playpos.unwrap();
+
+        unsafe { streamer_get_playpos() }
+    }
+
+    /// Index of the playlist being played from.
+    pub fn streamer_get_current_playlist() -> i32 {
+        let deadbeef = unsafe { DeadBeef::deadbeef() };
+        let streamer_get_current_playlist = deadbeef.get().streamer_get_current_playlist.unwrap();
+
+        unsafe { streamer_get_current_playlist() }
+    }
+
+    pub fn volume_get_db() -> f32 {
+        let deadbeef = unsafe { DeadBeef::deadbeef() };
+        let volume_get_db = deadbeef.get().volume_get_db.unwrap();
+
+        unsafe { volume_get_db() }
+    }
+
+    pub fn volume_set_db(db: f32) {
+        let deadbeef = unsafe { DeadBeef::deadbeef() };
+        let volume_set_db = deadbeef.get().volume_set_db.unwrap();
+
+        unsafe { volume_set_db(db) }
+    }
+
+    pub fn volume_get_min_db() -> f32 {
+        let deadbeef = unsafe { DeadBeef::deadbeef() };
+        let volume_get_min_db = deadbeef.get().volume_get_min_db.unwrap();
+
+        unsafe { volume_get_min_db() }
+    }
+
+    pub fn audio_is_mute() -> bool {
+        let deadbeef = unsafe { DeadBeef::deadbeef() };
+        let audio_is_mute = deadbeef.get().audio_is_mute.unwrap();
+
+        unsafe { audio_is_mute() != 0 }
+    }
+
+    pub fn audio_set_mute(mute: bool) {
+        let deadbeef = unsafe { DeadBeef::deadbeef() };
+        let audio_set_mute = deadbeef.get().audio_set_mute.unwrap();
+
+        unsafe { audio_set_mute(mute as i32) }
+    }
+
+    pub fn plt_get_count() -> i32 {
+        let deadbeef = unsafe { DeadBeef::deadbeef() };
+        let plt_get_count = deadbeef.get().plt_get_count.unwrap();
+
+        unsafe { plt_get_count() }
+    }
+
+    /// Switches the playlist shown in the UI.
+    pub fn plt_set_curr_idx(idx: i32) {
+        let deadbeef = unsafe { DeadBeef::deadbeef() };
+        let plt_set_curr_idx = deadbeef.get().plt_set_curr_idx.unwrap();
+
+        unsafe { plt_set_curr_idx(idx) }
+    }
+
+    /// Queues an item to play at position `n` of the play queue, 0 being next.
+    pub fn playqueue_insert_at(n: i32, it: &PlItem) {
+        let deadbeef = unsafe { DeadBeef::deadbeef() };
+        let playqueue_insert_at = deadbeef.get().playqueue_insert_at.unwrap();
+
+        unsafe { playqueue_insert_at(n, it.as_ptr()) }
+    }
+
     pub fn conf_find_str(key: impl AsRef<str>) -> Option<DBConfigurationItemIter> {
         let deadbeef = unsafe { DeadBeef::deadbeef() };
         let conf_find = deadbeef.get().conf_find.unwrap();
@@ -201,6 +340,38 @@ impl PlItem {
     fn as_ptr(&self) -> *mut DB_playItem_s {
         self.ptr.as_ptr()
     }
+
+    /// The item after this one in its playlist.
+    pub fn next(&self) -> Option<PlItem> {
+        let deadbeef = unsafe { DeadBeef::deadbeef() };
+        let pl_get_next = deadbeef.get().pl_get_next.unwrap();
+
+        PlItem::from_raw(unsafe { pl_get_next(self.as_ptr(), PL_MAIN) }).ok()
+    }
+
+    pub fn set_meta_int(&self, key: &str, value: i32) {
+        let deadbeef = unsafe { DeadBeef::deadbeef() };
+        let pl_set_meta_int = deadbeef.get().pl_set_meta_int.unwrap();
+        let key = LossyCString::new(key);
+
+        unsafe { pl_set_meta_int(self.as_ptr(), key.as_ptr(), value) }
+    }
+
+    pub fn delete_meta(&self, key: &str) {
+        let deadbeef = unsafe { DeadBeef::deadbeef() };
+        let pl_delete_meta = deadbeef.get().pl_delete_meta.unwrap();
+        let key = LossyCString::new(key);
+
+        unsafe { pl_delete_meta(self.as_ptr(), key.as_ptr()) }
+    }
+
+    /// The playlist this item belongs to, if any.
+    pub fn playlist(&self) -> Option<Playlist> {
+        let deadbeef = unsafe { DeadBeef::deadbeef() };
+        let pl_get_playlist = deadbeef.get().pl_get_playlist.unwrap();
+
+        Playlist::from_raw(unsafe { pl_get_playlist(self.as_ptr()) }).ok()
+    }
 }
 
 impl std::ops::Drop for PlItem {
@@ -209,6 +380,136 @@ impl std::ops::Drop for PlItem {
     }
 }
 
+pub struct Playlist {
+    ptr: ptr::NonNull<ddb_playlist_t>,
+}
+
+impl Playlist {
+    /// Takes ownership of a playlist reference returned by DeadBeef.
+    pub fn from_raw(fromptr: *mut ddb_playlist_t) -> Result<Self, DB_Error> {
+        let ptr: ptr::NonNull<ddb_playlist_t> =
+            ptr::NonNull::new(fromptr).ok_or(DB_Error::CreationFailed)?;
+        Ok(Self { ptr })
+    }
+
+    pub fn current() -> Option<Self> {
+        let deadbeef = unsafe { DeadBeef::deadbeef() };
+        let plt_get_curr = deadbeef.get().plt_get_curr.unwrap();
+
+        Self::from_raw(unsafe { plt_get_curr() }).ok()
+    }
+
+    pub fn find_by_name(name: &str) -> Option<Self> {
+        let deadbeef = unsafe { DeadBeef::deadbeef() };
+        let plt_find_by_name = deadbeef.get().plt_find_by_name.unwrap();
+        let name = LossyCString::new(name);
+
+        Self::from_raw(unsafe { plt_find_by_name(name.as_ptr()) }).ok()
+    }
+
+    pub fn idx(&self) -> i32 {
+        let deadbeef = unsafe { DeadBeef::deadbeef() };
+        let plt_get_idx = deadbeef.get().plt_get_idx.unwrap();
+
+        unsafe { plt_get_idx(self.as_ptr()) }
+    }
+
+    /// Marks the playlist as changed, so it gets saved.
+    pub fn modified(&self) {
+        let deadbeef = unsafe { DeadBeef::deadbeef() };
+        let plt_modified = deadbeef.get().plt_modified.unwrap();
+
+        unsafe { plt_modified(self.as_ptr()) }
+    }
+
+    /// Index of an item in this playlist, `None` if it isn't in it.
+    pub fn item_idx(&self, it: &PlItem) -> Option<i32> {
+        let deadbeef = unsafe { DeadBeef::deadbeef() };
+        let plt_get_item_idx = deadbeef.get().plt_get_item_idx.unwrap();
+
+        let idx = unsafe { plt_get_item_idx(self.as_ptr(), it.as_ptr(), PL_MAIN) };
+        (idx >= 0).then_some(idx)
+    }
+
+    pub fn as_ptr(&self) -> *mut ddb_playlist_t {
+        self.ptr.as_ptr()
+    }
+}
+
+impl std::ops::Drop for Playlist {
+    fn drop(&mut self) {
+        let deadbeef = unsafe { DeadBeef::deadbeef() };
+        let plt_unref = deadbeef.get().plt_unref.unwrap();
+
+        unsafe {
+            plt_unref(self.ptr.as_ptr());
+        }
+    }
+}
+
+/// A compiled title formatting script, like `%artist% - %title%`.
+pub struct TitleFormat {
+    code: ptr::NonNull<std::ffi::c_char>,
+}
+
+// The bytecode is only read after compiling.
+unsafe impl Send for TitleFormat {}
+unsafe impl Sync for TitleFormat {}
+
+impl TitleFormat {
+    pub fn compile(script: &str) -> Result<Self, DB_TF_Error> {
+        let deadbeef = unsafe { DeadBeef::deadbeef() };
+        let tf_compile = deadbeef.get().tf_compile.unwrap();
+        let script = LossyCString::new(script);
+
+        let code = unsafe { tf_compile(script.as_ptr()) };
+        let code = ptr::NonNull::new(code).ok_or(DB_TF_Error::CompileError)?;
+        Ok(Self { code })
+    }
+
+    /// Evaluates the script for a track and the playlist it is in, either
+    /// may be missing.
+    pub fn eval(&self, it: Option<&PlItem>, plt: Option<&Playlist>) -> Result<String, DB_TF_Error> {
+        let deadbeef = unsafe { DeadBeef::deadbeef() };
+        let tf_eval = deadbeef.get().tf_eval.unwrap();
+
+        let mut ctx = ddb_tf_context_t {
+            _size: std::mem::size_of::<ddb_tf_context_t>() as i32,
+            it: it.map_or(ptr::null_mut(), |it| it.as_ptr()),
+            plt: plt.map_or(ptr::null_mut(), |p| p.as_ptr()),
+            ..Default::default()
+        };
+        let mut buf: Vec<u8> = vec![0; 4096];
+
+        let len = unsafe {
+            tf_eval(
+                &mut ctx,
+                self.code.as_ptr(),
+                buf.as_mut_ptr() as *mut std::ffi::c_char,
+                buf.len() as i32,
+            )
+        };
+        if len < 0 {
+            return Err(DB_TF_Error::EvalError);
+        }
+
+        let cstr =
+            std::ffi::CStr::from_bytes_until_nul(&buf).map_err(|_| DB_TF_Error::EvalError)?;
+        Ok(cstr.to_string_lossy().into_owned())
+    }
+}
+
+impl std::ops::Drop for TitleFormat {
+    fn drop(&mut self) {
+        let deadbeef = unsafe { DeadBeef::deadbeef() };
+        let tf_free = deadbeef.get().tf_free.unwrap();
+
+        unsafe {
+            tf_free(self.code.as_ptr());
+        }
+    }
+}
+
 #[derive(Copy, Clone, PartialEq, Eq)]
 pub struct PlaybackState(ddb_playback_state_e);
 
@@ -1,6 +1,6 @@
 use std::ffi::CStr;
 
-use crate::{ddb_action_context_e, DB_plugin_action_t, DB_plugin_t};
+use crate::{ddb_action_context_e, DB_Error, DB_plugin_action_t, DB_plugin_t};
 
 pub struct Plugin {
     ptr: *mut DB_plugin_t,
@@ -80,6 +80,13 @@ impl Plugin {
 }
 
 impl Action {
+    pub fn from_raw(ptr: *mut DB_plugin_action_t) -> Result<Self, DB_Error> {
+        if ptr.is_null() {
+            return Err(DB_Error::CreationFailed);
+        }
+        Ok(Self { ptr })
+    }
+
     pub fn as_ptr(&self) -> *mut DB_plugin_action_t {
         self.ptr
     }
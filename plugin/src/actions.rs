@@ -0,0 +1,135 @@
+use deadbeef_sys::*;
+use std::ffi::{c_int, CString};
+
+pub type Callback = unsafe extern "C" fn(*mut DB_plugin_action_t, ddb_action_context_t) -> c_int;
+
+/// Linked list of the plugin's own actions, handed to DeadBeef through
+/// `get_actions`.
+#[derive(Default)]
+pub struct ActionList {
+    entries: Vec<(String, String)>,
+    /// Never grown once linked, so the pointers DeadBeef gets stay put.
+    actions: Vec<DB_plugin_action_t>,
+    /// Titles and names the actions point into.
+    strings: Vec<CString>,
+    /// Replaced lists. DeadBeef may hold on to their pointers until it has
+    /// handled `DB_EV_ACTIONSCHANGED`, so they are kept alive until then, see
+    /// [`Self::release_retired`].
+    retired: Vec<ActionList>,
+}
+
+unsafe impl Send for ActionList {}
+
+/// Names and titles of actions to offer for the action names of the bindings,
+/// under the name they are bound by so DeadBeef finds them. `title` gives the
+/// title of the names that are the kind of action being offered.
+pub fn bound_actions<'a>(
+    bound: impl IntoIterator<Item = &'a str>,
+    title: impl Fn(&str) -> Option<String>,
+) -> Vec<(String, String)> {
+    let mut actions: Vec<(String, String)> = Vec::new();
+    for name in bound {
+        if actions.iter().any(|(n, _)| n == name) {
+            continue;
+        }
+        if let Some(title) = title(name) {
+            actions.push((name.to_owned(), title));
+        }
+    }
+    actions
+}
+
+impl ActionList {
+    /// Replaces the actions with `(name, title)` pairs all running `callback`,
+    /// returning whether they changed.
+    pub fn set(&mut self, actions: &[(String, String)], callback: Callback) -> bool {
+        if self.entries == actions {
+            return false;
+        }
+
+        let mut list = ActionList {
+            entries: actions.to_vec(),
+            actions: Vec::with_capacity(actions.len()),
+            ..Default::default()
+        };
+        for (name, title) in actions {
+            let name = CString::new(name.as_str()).unwrap_or_default();
+            let title = CString::new(title.as_str()).unwrap_or_default();
+            list.actions.push(DB_plugin_action_t {
+                title: title.as_ptr(),
+                name: name.as_ptr(),
+                flags: DB_ACTION_COMMON,
+                callback: None,
+                next: std::ptr::null_mut(),
+                callback2: Some(callback),
+            });
+            list.strings.extend([name, title]);
+        }
+        for i in 1..list.actions.len() {
+            let next: *mut DB_plugin_action_t = &mut list.actions[i];
+            list.actions[i - 1].next = next;
+        }
+
+        let mut old = std::mem::replace(self, list);
+        self.retired = std::mem::take(&mut old.retired);
+        self.retired.push(old);
+        true
+    }
+
+    /// Frees the replaced lists, once `DB_EV_ACTIONSCHANGED` went around.
+    pub fn release_retired(&mut self) {
+        self.retired.clear();
+    }
+
+    pub fn head(&mut self) -> *mut DB_plugin_action_t {
+        self.actions.first_mut().map_or(std::ptr::null_mut(), |a| a)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    unsafe extern "C" fn noop(_: *mut DB_plugin_action_t, _: ddb_action_context_t) -> c_int {
+        0
+    }
+
+    fn names(list: &mut ActionList) -> Vec<String> {
+        let mut names = Vec::new();
+        let mut current = list.head();
+        while let Ok(action) = plugin::Action::from_raw(current) {
+            names.push(action.name().unwrap().to_owned());
+            current = unsafe { (*current).next };
+        }
+        names
+    }
+
+    #[test]
+    fn links_actions_and_keeps_old_lists() {
+        let mut list = ActionList::default();
+        assert!(list.head().is_null());
+
+        let actions = [
+            ("a".to_owned(), "Hotkeys/A".to_owned()),
+            ("b".to_owned(), "Hotkeys/B".to_owned()),
+        ];
+        assert!(list.set(&actions, noop));
+        assert_eq!(names(&mut list), ["a", "b"]);
+        let old_head = list.head();
+
+        assert!(!list.set(&actions, noop));
+        assert_eq!(list.head(), old_head);
+
+        assert!(list.set(&actions[1..], noop));
+        assert_eq!(names(&mut list), ["b"]);
+        // Still valid
+        let old = plugin::Action::from_raw(old_head).unwrap();
+        assert_eq!(old.name(), Some("a"));
+
+        assert!(list.set(&actions, noop));
+        assert!(!list.retired.is_empty());
+        list.release_retired();
+        assert!(list.retired.is_empty());
+        assert_eq!(names(&mut list), ["a", "b"]);
+    }
+}
@@ -0,0 +1,171 @@
+use crate::{
+    keystroke::Keystroke, shortcuthandler::PortalBackend, utils::log_to_deadbeef, x11::X11Backend,
+};
+use deadbeef_sys::{DeadBeef, DDB_LOG_LAYER_DEFAULT, DDB_LOG_LAYER_INFO};
+use std::{ffi::CStr, str::FromStr, sync::Arc, time::Instant};
+
+/// Config key with the backends to try, `auto`, `none` or a comma separated
+/// list like `x11,evdev`.
+pub const CONF_BACKEND: &str = "hotkeys.backend";
+
+/// Order tried by `auto`.
+const AUTO_CHAIN: &[Kind] = &[Kind::Portal, Kind::X11, Kind::Evdev];
+
+/// Called with the keystroke when a backend sees a grabbed keystroke
+/// being pressed, and with the same keystroke again when its key is released.
+///
+/// Returns a deadline while a key sequence is in progress, until which the
+/// backend should report every keystroke, not just the grabbed ones.
+pub type Dispatch = Arc<dyn Fn(Keystroke, KeyEvent) -> Option<Instant> + Send + Sync>;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum KeyEvent {
+    Press,
+    /// Not sent for key repeat.
+    Release,
+}
+
+/// A way of capturing global hotkeys.
+pub trait Backend: Send {
+    fn kind(&self) -> Kind;
+
+    /// Replaces the keystrokes to grab, e.g. when the binding layer changes.
+    /// Backends that don't grab keys themselves keep their bindings.
+    fn set_keys(&self, _keys: &[Keystroke]) {}
+
+    /// Releases everything the backend grabbed and waits for its worker thread.
+    fn stop(self: Box<Self>);
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Kind {
+    Portal,
+    X11,
+    Evdev,
+}
+
+impl Kind {
+    pub fn name(self) -> &'static str {
+        self.as_cstr().to_str().unwrap()
+    }
+
+    pub fn as_cstr(self) -> &'static CStr {
+        match self {
+            Kind::Portal => c"portal",
+            Kind::X11 => c"x11",
+            Kind::Evdev => c"evdev",
+        }
+    }
+}
+
+impl FromStr for Kind {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.trim().to_ascii_lowercase().as_str() {
+            "portal" => Ok(Kind::Portal),
+            "x11" => Ok(Kind::X11),
+            "evdev" => Ok(Kind::Evdev),
+            other => Err(format!("unknown backend \"{other}\"")),
+        }
+    }
+}
+
+/// Parses the value of [`CONF_BACKEND`] into the backends to try, in order.
+pub fn parse_chain(value: &str) -> Result<Vec<Kind>, String> {
+    match value.trim().to_ascii_lowercase().as_str() {
+        "" | "auto" => Ok(AUTO_CHAIN.to_vec()),
+        "none" => Ok(Vec::new()),
+        list => {
+            let mut chain = Vec::new();
+            for kind in list.split(',').map(Kind::from_str) {
+                let kind = kind?;
+                if !chain.contains(&kind) {
+                    chain.push(kind);
+                }
+            }
+            Ok(chain)
+        }
+    }
+}
+
+/// Starts the first backend of the configured chain that works in this session.
+///
+/// `keys` are the global keystrokes for backends that grab
+/// keys themselves; the portal reads its bindings from the config.
+pub fn start(keys: &[Keystroke], dispatch: Dispatch) -> Option<Box<dyn Backend>> {
+    let value = DeadBeef::conf_get_str(CONF_BACKEND, "auto");
+    let chain = parse_chain(&value).unwrap_or_else(|e| {
+        log_to_deadbeef(
+            DDB_LOG_LAYER_DEFAULT,
+            &format!("{CONF_BACKEND}: {e}, falling back to auto"),
+        );
+        AUTO_CHAIN.to_vec()
+    });
+
+    if chain.is_empty() {
+        log_to_deadbeef(DDB_LOG_LAYER_INFO, "global hotkeys disabled");
+        return None;
+    }
+
+    for kind in chain {
+        match start_kind(kind, keys, dispatch.clone()) {
+            Ok(backend) => {
+                let msg = format!("using {} backend for global hotkeys", kind.name());
+                log_to_deadbeef(DDB_LOG_LAYER_INFO, &msg);
+                return Some(backend);
+            }
+            Err(e) => {
+                let msg = format!("{} backend rejected: {e}", kind.name());
+                log_to_deadbeef(DDB_LOG_LAYER_DEFAULT, &msg);
+            }
+        }
+    }
+
+    log_to_deadbeef(
+        DDB_LOG_LAYER_DEFAULT,
+        "no backend available, global hotkeys won't work",
+    );
+    None
+}
+
+fn start_kind(
+    kind: Kind,
+    keys: &[Keystroke],
+    dispatch: Dispatch,
+) -> Result<Box<dyn Backend>, String> {
+    match kind {
+        Kind::Portal => {
+            PortalBackend::probe().map_err(|e| e.to_string())?;
+            Ok(Box::new(PortalBackend::new()))
+        }
+        Kind::X11 => X11Backend::new(keys, dispatch)
+            .map(|b| Box::new(b) as Box<dyn Backend>)
+            .map_err(|e| e.to_string()),
+        #[cfg(feature = "evdev")]
+        Kind::Evdev => crate::evdev::EvdevBackend::new(keys, dispatch)
+            .map(|b| Box::new(b) as Box<dyn Backend>)
+            .map_err(|e| e.to_string()),
+        #[cfg(not(feature = "evdev"))]
+        Kind::Evdev => {
+            let _ = dispatch;
+            Err("built without the evdev feature".to_owned())
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{parse_chain, Kind::*};
+
+    #[test]
+    fn parses_backend_chains() {
+        assert_eq!(parse_chain("auto"), Ok(vec![Portal, X11, Evdev]));
+        assert_eq!(parse_chain(""), Ok(vec![Portal, X11, Evdev]));
+        assert_eq!(parse_chain("none"), Ok(vec![]));
+        assert_eq!(parse_chain("x11"), Ok(vec![X11]));
+        assert_eq!(parse_chain(" X11 , evdev,x11"), Ok(vec![X11, Evdev]));
+        assert!(parse_chain("x11,wayland").is_err());
+        assert!(parse_chain("x11,").is_err());
+    }
+}
@@ -0,0 +1,325 @@
+use crate::{
+    actions::bound_actions,
+    utils::{escape_title, percent_decode, percent_encode},
+};
+use deadbeef_sys::{
+    DeadBeef, PlItem, Playlist, TitleFormat, DB_EV_NEXT, DB_EV_PLAYLISTCHANGED, DB_EV_PLAY_NUM,
+    DB_EV_SEEK, DDB_PLAYLIST_CHANGE_CONTENT,
+};
+use std::fmt;
+
+const PREFIX: &str = "hotkeys_";
+/// Between an action's name and its parameter.
+const PARAM_SEPARATOR: char = ':';
+
+/// Actions this plugin provides itself, with their parameter after a colon in
+/// the action name, like `"Ctrl Right" 0 1 hotkeys_seek:+10`. The name has to
+/// stay a single token, so spaces, `%`, `;`, `,` and `"` in the parameter are
+/// written as `%XX` escapes: `hotkeys_playlist:Late%20radio`.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Builtin {
+    /// Seconds from the playing position, negative to go back.
+    Seek(f32),
+    /// In dB, 0 is full volume.
+    SetVolume(f32),
+    /// In dB.
+    VolumeStep(f32),
+    /// Switches to a playlist, counting from 1.
+    Playlist(i32),
+    /// Switches to the playlist with this title.
+    PlaylistNamed(String),
+    /// Plays an item of the current playlist, counting from 1.
+    PlayItem(i32),
+    ToggleMute,
+    /// Skips the rest of the playing album.
+    NextAlbum,
+    /// Rates the playing track from 1 to 5 stars, 0 clears the rating.
+    Rate(u8),
+}
+
+/// Stars, as the rating column shows them.
+const MAX_RATING: u8 = 5;
+
+/// The ones without a parameter, offered even when nothing is bound to them.
+const FIXED: [Builtin; 2] = [Builtin::ToggleMute, Builtin::NextAlbum];
+
+impl Builtin {
+    /// Parses the action of a binding line, `None` if it isn't a built-in one.
+    pub fn parse(action: &str) -> Option<Result<Self, String>> {
+        let action = action.trim();
+        let (name, raw) = match action.split_once(PARAM_SEPARATOR) {
+            Some((name, raw)) => (name, Some(raw)),
+            None => (action, None),
+        };
+        let name = name.strip_prefix(PREFIX)?;
+
+        let param = |what: &str| -> Result<String, String> {
+            let raw = raw.ok_or_else(|| format!("{action}: expected {what}"))?;
+            percent_decode(raw).map_err(|e| format!("{action}: {e}"))
+        };
+        let number = |what: &str| -> Result<f32, String> {
+            let arg = param(what)?;
+            arg.parse::<f32>()
+                .ok()
+                .filter(|n| n.is_finite())
+                .ok_or_else(|| format!("{action}: invalid {what} \"{arg}\""))
+        };
+        let index = |what: &str| -> Result<i32, String> {
+            let arg = param(what)?;
+            arg.parse::<i32>()
+                .ok()
+                .filter(|&n| n > 0)
+                .ok_or_else(|| format!("{action}: invalid {what} \"{arg}\""))
+        };
+        let none = |builtin: Builtin| match raw {
+            None => Ok(builtin),
+            Some(_) => Err(format!("{action}: takes no parameter")),
+        };
+
+        Some(match name {
+            "seek" => number("seconds").map(Builtin::Seek),
+            "set_volume" => number("dB").map(Builtin::SetVolume),
+            "volume_step" => number("dB").map(Builtin::VolumeStep),
+            "playlist" => param("playlist number or title").and_then(|arg| {
+                if arg.parse::<i32>().is_ok() {
+                    index("playlist number").map(Builtin::Playlist)
+                } else if arg.is_empty() {
+                    Err(format!("{action}: expected playlist number or title"))
+                } else {
+                    Ok(Builtin::PlaylistNamed(arg))
+                }
+            }),
+            "play_item" => index("item number").map(Builtin::PlayItem),
+            "rate" => param("rating").and_then(|arg| {
+                arg.parse::<u8>()
+                    .ok()
+                    .filter(|&n| n <= MAX_RATING)
+                    .map(Builtin::Rate)
+                    .ok_or_else(|| format!("{action}: invalid rating \"{arg}\", expected 0 to 5"))
+            }),
+            "toggle_mute" => none(Builtin::ToggleMute),
+            "next_album" => none(Builtin::NextAlbum),
+            _ => return None,
+        })
+    }
+
+    /// The action name in a binding line.
+    pub fn action_name(&self) -> String {
+        let (name, arg) = match self {
+            Builtin::Seek(s) => ("seek", Some(format!("{s:+}"))),
+            Builtin::SetVolume(db) => ("set_volume", Some(db.to_string())),
+            Builtin::VolumeStep(db) => ("volume_step", Some(format!("{db:+}"))),
+            Builtin::Playlist(n) => ("playlist", Some(n.to_string())),
+            Builtin::PlaylistNamed(title) => ("playlist", Some(title.clone())),
+            Builtin::PlayItem(n) => ("play_item", Some(n.to_string())),
+            Builtin::ToggleMute => ("toggle_mute", None),
+            Builtin::NextAlbum => ("next_album", None),
+            Builtin::Rate(n) => ("rate", Some(n.to_string())),
+        };
+        match arg {
+            Some(arg) => format!("{PREFIX}{name}{PARAM_SEPARATOR}{}", percent_encode(&arg)),
+            None => format!("{PREFIX}{name}"),
+        }
+    }
+
+    pub fn run(&self) {
+        match *self {
+            Builtin::Seek(secs) => {
+                if DeadBeef::streamer_get_playing_track().is_none() {
+                    return;
+                }
+                let pos = (DeadBeef::streamer_get_playpos() + secs).max(0.0);
+                DeadBeef::sendmessage(DB_EV_SEEK, 0, (pos * 1000.0) as u32, 0);
+            }
+            Builtin::SetVolume(db) => set_volume(db),
+            Builtin::VolumeStep(db) => set_volume(DeadBeef::volume_get_db() + db),
+            Builtin::Playlist(n) => {
+                if n <= DeadBeef::plt_get_count() {
+                    DeadBeef::plt_set_curr_idx(n - 1);
+                } else {
+                    tracing::debug!("No playlist {n}");
+                }
+            }
+            Builtin::PlaylistNamed(ref title) => match Playlist::find_by_name(title) {
+                Some(plt) => DeadBeef::plt_set_curr_idx(plt.idx()),
+                None => tracing::debug!("No playlist \"{title}\""),
+            },
+            Builtin::PlayItem(n) => {
+                DeadBeef::sendmessage(DB_EV_PLAY_NUM, 0, (n - 1) as u32, 0);
+            }
+            Builtin::ToggleMute => DeadBeef::audio_set_mute(!DeadBeef::audio_is_mute()),
+            Builtin::NextAlbum => next_album(),
+            Builtin::Rate(n) => rate(n),
+        }
+    }
+}
+
+impl fmt::Display for Builtin {
+    /// The action title.
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Builtin::Seek(s) => write!(f, "Hotkeys/Seek {s:+} s"),
+            Builtin::SetVolume(db) => write!(f, "Hotkeys/Set volume to {db} dB"),
+            Builtin::VolumeStep(db) => write!(f, "Hotkeys/Volume {db:+} dB"),
+            Builtin::Playlist(n) => write!(f, "Hotkeys/Switch to playlist {n}"),
+            Builtin::PlaylistNamed(title) => {
+                write!(f, "Hotkeys/Switch to playlist {}", escape_title(title))
+            }
+            Builtin::PlayItem(n) => write!(f, "Hotkeys/Play item {n}"),
+            Builtin::ToggleMute => write!(f, "Hotkeys/Toggle mute"),
+            Builtin::NextAlbum => write!(f, "Hotkeys/Next album"),
+            Builtin::Rate(0) => write!(f, "Hotkeys/Clear rating"),
+            Builtin::Rate(n) => write!(f, "Hotkeys/Rate {n}"),
+        }
+    }
+}
+
+/// The built-in actions to offer, see [`bound_actions`], along with the
+/// [`FIXED`] ones.
+pub fn builtin_actions<'a>(bound: impl IntoIterator<Item = &'a str>) -> Vec<(String, String)> {
+    let fixed: Vec<_> = FIXED.iter().map(Builtin::action_name).collect();
+    let mut names: Vec<&str> = fixed.iter().map(String::as_str).collect();
+    // Pushed one by one, extending would need `fixed` to live for 'a
+    for name in bound {
+        names.push(name);
+    }
+    bound_actions(names, |name| Some(Builtin::parse(name)?.ok()?.to_string()))
+}
+
+fn set_volume(db: f32) {
+    DeadBeef::volume_set_db(db.clamp(DeadBeef::volume_get_min_db(), 0.0));
+}
+
+fn next_album() {
+    let Some(playing) = DeadBeef::streamer_get_playing_track() else {
+        return;
+    };
+    let Some(plt) = playing.playlist() else {
+        return;
+    };
+    let tf = match TitleFormat::compile("%album artist%|%album%") {
+        Ok(tf) => tf,
+        Err(e) => {
+            tracing::error!("{e}");
+            return;
+        }
+    };
+    let album = |it: &PlItem| tf.eval(Some(it), Some(&plt)).ok();
+
+    let current = album(&playing);
+    let mut next = playing.next();
+    while let Some(it) = next {
+        if album(&it) != current {
+            // Queued items play from their own playlist, whichever one is
+            // shown, and with DB_EV_PLAY_NUM that would be the shown one.
+            DeadBeef::playqueue_insert_at(0, &it);
+            DeadBeef::sendmessage(DB_EV_NEXT, 0, 0, 0);
+            return;
+        }
+        next = it.next();
+    }
+    tracing::debug!("No album after the playing one");
+}
+
+/// Sets the `rating` tag of the playing track, which the rating column shows.
+fn rate(stars: u8) {
+    let Some(playing) = DeadBeef::streamer_get_playing_track() else {
+        return;
+    };
+    if stars == 0 {
+        playing.delete_meta("rating");
+    } else {
+        playing.set_meta_int("rating", stars.into());
+    }
+    if let Some(plt) = playing.playlist() {
+        plt.modified();
+    }
+    DeadBeef::sendmessage(DB_EV_PLAYLISTCHANGED, 0, DDB_PLAYLIST_CHANGE_CONTENT, 0);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_builtins() {
+        let table = [
+            ("hotkeys_seek:+10", Builtin::Seek(10.0)),
+            ("hotkeys_seek:-2.5", Builtin::Seek(-2.5)),
+            ("hotkeys_set_volume:-12", Builtin::SetVolume(-12.0)),
+            ("hotkeys_volume_step:2", Builtin::VolumeStep(2.0)),
+            ("hotkeys_playlist:3", Builtin::Playlist(3)),
+            (
+                "hotkeys_playlist:Late%20radio",
+                Builtin::PlaylistNamed("Late radio".to_owned()),
+            ),
+            (
+                "hotkeys_playlist:A:B",
+                Builtin::PlaylistNamed("A:B".to_owned()),
+            ),
+            ("hotkeys_play_item:1", Builtin::PlayItem(1)),
+            ("hotkeys_toggle_mute", Builtin::ToggleMute),
+            ("hotkeys_next_album", Builtin::NextAlbum),
+            ("hotkeys_rate:4", Builtin::Rate(4)),
+            ("hotkeys_rate:0", Builtin::Rate(0)),
+        ];
+        for (action, builtin) in table {
+            assert_eq!(Builtin::parse(action), Some(Ok(builtin)), "{action:?}");
+        }
+
+        for action in [
+            "hotkeys_seek",
+            "hotkeys_seek:fast",
+            "hotkeys_seek:1%202",
+            "hotkeys_seek:inf",
+            "hotkeys_playlist:0",
+            "hotkeys_playlist",
+            "hotkeys_playlist:",
+            "hotkeys_playlist:Late%2",
+            "hotkeys_toggle_mute:1",
+            "hotkeys_rate",
+            "hotkeys_rate:6",
+            "hotkeys_rate:-1",
+        ] {
+            assert!(matches!(Builtin::parse(action), Some(Err(_))), "{action:?}");
+        }
+
+        assert_eq!(Builtin::parse("seek_forward"), None);
+        assert_eq!(Builtin::parse("hotkeys_default_layer"), None);
+    }
+
+    #[test]
+    fn offers_bound_builtins_by_their_names() {
+        let actions =
+            builtin_actions(["hotkeys_seek:10", "hotkeys_seek:10", "hotkeys_toggle_mute"]);
+        assert_eq!(
+            actions,
+            [
+                (
+                    "hotkeys_toggle_mute".to_owned(),
+                    "Hotkeys/Toggle mute".to_owned()
+                ),
+                (
+                    "hotkeys_next_album".to_owned(),
+                    "Hotkeys/Next album".to_owned()
+                ),
+                (
+                    "hotkeys_seek:10".to_owned(),
+                    "Hotkeys/Seek +10 s".to_owned()
+                ),
+            ]
+        );
+        for builtin in [
+            Builtin::VolumeStep(-2.0),
+            Builtin::PlaylistNamed("50% off; \"live\"".to_owned()),
+        ] {
+            let name = builtin.action_name();
+            assert!(!name.contains(char::is_whitespace), "{name:?}");
+            assert_eq!(Builtin::parse(&name), Some(Ok(builtin)));
+        }
+        // Parameters used to follow a space
+        assert_eq!(Builtin::parse("hotkeys_seek +10"), None);
+        assert_eq!(Builtin::Rate(3).to_string(), "Hotkeys/Rate 3");
+        assert_eq!(Builtin::Rate(0).to_string(), "Hotkeys/Clear rating");
+    }
+}
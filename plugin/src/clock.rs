@@ -0,0 +1,126 @@
+use smol::{Task, Timer};
+use std::{future::Future, pin::Pin, time::Duration};
+
+pub type Sleep = Pin<Box<dyn Future<Output = ()> + Send>>;
+
+/// Where the timed parts of trigger modes and macros run.
+pub trait Clock: Clone + Send + Sync + 'static {
+    /// Starts a task, cancelled when the returned handle is dropped.
+    fn spawn(&self, future: impl Future<Output = ()> + Send + 'static) -> Task<()>;
+
+    /// Completes once `d` has passed.
+    fn sleep(&self, d: Duration) -> Sleep;
+}
+
+/// smol's global executor and timers. Its threads run the actions off the
+/// plugin thread and the key handlers, so a long macro doesn't hold them up.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct SmolClock;
+
+impl Clock for SmolClock {
+    fn spawn(&self, future: impl Future<Output = ()> + Send + 'static) -> Task<()> {
+        smol::spawn(future)
+    }
+
+    fn sleep(&self, d: Duration) -> Sleep {
+        Box::pin(async move {
+            Timer::after(d).await;
+        })
+    }
+}
+
+#[cfg(test)]
+pub use manual::ManualClock;
+
+#[cfg(test)]
+mod manual {
+    use super::{Clock, Sleep};
+    use smol::{Executor, Task};
+    use std::{
+        future::Future,
+        pin::Pin,
+        sync::{Arc, Mutex},
+        task::{Context, Poll, Waker},
+        time::Duration,
+    };
+
+    #[derive(Default)]
+    struct State {
+        now: Duration,
+        sleeping: Vec<(Duration, Waker)>,
+    }
+
+    /// Time that only passes through [`Self::advance`], with the tasks run on
+    /// the calling thread meanwhile.
+    #[derive(Clone, Default)]
+    pub struct ManualClock {
+        executor: Arc<Executor<'static>>,
+        state: Arc<Mutex<State>>,
+    }
+
+    impl ManualClock {
+        /// Moves time forward by `d`, running every task that gets due on the
+        /// way in the order of their deadlines.
+        pub fn advance(&self, d: Duration) {
+            let target = self.state.lock().unwrap().now + d;
+            loop {
+                while self.executor.try_tick() {}
+
+                let mut state = self.state.lock().unwrap();
+                let Some(at) = state
+                    .sleeping
+                    .iter()
+                    .map(|&(at, _)| at)
+                    .filter(|&at| at <= target)
+                    .min()
+                else {
+                    break;
+                };
+                state.now = at;
+                let (due, sleeping) = std::mem::take(&mut state.sleeping)
+                    .into_iter()
+                    .partition(|&(due, _)| due <= at);
+                state.sleeping = sleeping;
+                drop(state);
+
+                for (_, waker) in due {
+                    waker.wake();
+                }
+            }
+            self.state.lock().unwrap().now = target;
+        }
+    }
+
+    impl Clock for ManualClock {
+        fn spawn(&self, future: impl Future<Output = ()> + Send + 'static) -> Task<()> {
+            self.executor.spawn(future)
+        }
+
+        fn sleep(&self, d: Duration) -> Sleep {
+            let at = self.state.lock().unwrap().now + d;
+            Box::pin(ManualSleep {
+                at,
+                state: self.state.clone(),
+            })
+        }
+    }
+
+    struct ManualSleep {
+        at: Duration,
+        state: Arc<Mutex<State>>,
+    }
+
+    impl Future for ManualSleep {
+        type Output = ();
+
+        fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<()> {
+            let mut state = self.state.lock().unwrap();
+            if state.now >= self.at {
+                Poll::Ready(())
+            } else {
+                state.sleeping.push((self.at, cx.waker().clone()));
+                Poll::Pending
+            }
+        }
+    }
+}
@@ -0,0 +1,88 @@
+use deadbeef_sys::{DeadBeef, PlItem, TitleFormat};
+use std::{fmt, sync::Arc};
+
+/// Splits a condition off a keystroke from the config, written last after a
+/// `?` like `"Space ? %ispaused%"`.
+pub fn split_condition(keystroke: &str) -> Result<(&str, Option<&str>), String> {
+    let Some((keys, script)) = keystroke.split_once('?') else {
+        return Ok((keystroke.trim(), None));
+    };
+    let script = script.trim();
+    if script.is_empty() {
+        return Err("empty condition".to_string());
+    }
+    Ok((keys.trim(), Some(script)))
+}
+
+/// Inverse of [`split_condition`].
+pub fn join_condition(keys: &str, script: Option<&str>) -> String {
+    match script {
+        Some(script) => format!("{keys} ? {script}"),
+        None => keys.to_owned(),
+    }
+}
+
+/// A title formatting script deciding whether a binding applies. It holds
+/// when it gives a non-empty result for the playing track, so `%ispaused%`
+/// or `$if($stricmp(%genre%,Podcast),1)` work as is.
+#[derive(Clone)]
+pub struct Condition {
+    script: String,
+    tf: Arc<TitleFormat>,
+}
+
+impl Condition {
+    pub fn compile(script: &str) -> Result<Self, String> {
+        let tf =
+            TitleFormat::compile(script).map_err(|e| format!("{e} in condition \"{script}\""))?;
+        Ok(Self {
+            script: script.to_owned(),
+            tf: Arc::new(tf),
+        })
+    }
+
+    pub fn script(&self) -> &str {
+        &self.script
+    }
+
+    pub fn holds(&self) -> bool {
+        let it = DeadBeef::streamer_get_playing_track();
+        let plt = it.as_ref().and_then(PlItem::playlist);
+        match self.tf.eval(it.as_ref(), plt.as_ref()) {
+            Ok(result) => !result.is_empty(),
+            Err(e) => {
+                tracing::debug!("{e} in condition \"{}\"", self.script);
+                false
+            }
+        }
+    }
+}
+
+impl fmt::Debug for Condition {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_tuple("Condition").field(&self.script).finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn splits_conditions() {
+        assert_eq!(split_condition("Space"), Ok(("Space", None)));
+        assert_eq!(
+            split_condition("Space @long-press ? %ispaused%"),
+            Ok(("Space @long-press", Some("%ispaused%")))
+        );
+        assert_eq!(
+            split_condition("p?$if(%rating%,a?b)"),
+            Ok(("p", Some("$if(%rating%,a?b)")))
+        );
+        assert!(split_condition("Space ? ").is_err());
+        assert_eq!(
+            join_condition("Space", Some("%ispaused%")),
+            "Space ? %ispaused%"
+        );
+    }
+}
@@ -0,0 +1,187 @@
+use crate::keystroke::Keystroke;
+use std::fmt;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ConflictKind {
+    /// Both are bound to the same keys, only the first one runs.
+    Duplicate,
+    /// The second one starts with the keys of the first, which runs instead.
+    Prefix,
+    /// The same keys are bound locally and globally, the global binding takes
+    /// them while the backend grabs them.
+    LocalGlobal,
+}
+
+impl ConflictKind {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            ConflictKind::Duplicate => "duplicate",
+            ConflictKind::Prefix => "prefix",
+            ConflictKind::LocalGlobal => "local-global",
+        }
+    }
+}
+
+/// Two bindings getting in each other's way, by their config keys.
+#[derive(Debug, PartialEq, Eq)]
+pub struct Conflict {
+    pub kind: ConflictKind,
+    pub first: String,
+    pub second: String,
+}
+
+impl fmt::Display for Conflict {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let Conflict {
+            kind,
+            first,
+            second,
+        } = self;
+        match kind {
+            ConflictKind::Duplicate => {
+                write!(
+                    f,
+                    "{second} is bound to the same keys as {first} and never runs"
+                )
+            }
+            ConflictKind::Prefix => {
+                write!(f, "{second} starts with the keys of {first} and never runs")
+            }
+            ConflictKind::LocalGlobal => {
+                write!(
+                    f,
+                    "{first} and {second} bind the same keys locally and globally"
+                )
+            }
+        }
+    }
+}
+
+/// What conflict detection needs to know about a binding.
+#[derive(Debug, Clone, Copy)]
+pub struct Binding<'a> {
+    pub id: &'a str,
+    pub sequence: &'a [Keystroke],
+    pub isglobal: bool,
+    pub layer: Option<&'a str>,
+    pub condition: Option<&'a str>,
+}
+
+/// Finds the bindings that shadow each other, given in config order.
+///
+/// Bindings in different layers or with different conditions are meant to
+/// share keys and don't conflict.
+pub fn find_conflicts(bindings: &[Binding]) -> Vec<Conflict> {
+    let mut conflicts = Vec::new();
+    for (i, a) in bindings.iter().enumerate() {
+        for b in &bindings[i + 1..] {
+            if a.layer != b.layer || a.condition != b.condition {
+                continue;
+            }
+            let (kind, first, second) = if a.isglobal != b.isglobal {
+                if a.sequence != b.sequence {
+                    continue;
+                }
+                (ConflictKind::LocalGlobal, a, b)
+            } else if a.sequence == b.sequence {
+                (ConflictKind::Duplicate, a, b)
+            } else if b.sequence.starts_with(a.sequence) {
+                (ConflictKind::Prefix, a, b)
+            } else if a.sequence.starts_with(b.sequence) {
+                (ConflictKind::Prefix, b, a)
+            } else {
+                continue;
+            };
+            conflicts.push(Conflict {
+                kind,
+                first: first.id.to_owned(),
+                second: second.id.to_owned(),
+            });
+        }
+    }
+    conflicts
+}
+
+/// The conflicts as text, a `kind\tfirst\tsecond` line for each.
+pub fn report(conflicts: &[Conflict]) -> String {
+    conflicts
+        .iter()
+        .map(|c| format!("{}\t{}\t{}\n", c.kind.as_str(), c.first, c.second))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const CTRL_X: Keystroke = Keystroke::from_raw(0x78, 4);
+    const N: Keystroke = Keystroke::from_raw(0x6e, 0);
+
+    fn binding<'a>(id: &'a str, sequence: &'a [Keystroke], isglobal: bool) -> Binding<'a> {
+        Binding {
+            id,
+            sequence,
+            isglobal,
+            layer: None,
+            condition: None,
+        }
+    }
+
+    #[test]
+    fn finds_conflicts() {
+        let bindings = [
+            binding("hotkey.key01", &[CTRL_X, N], true),
+            binding("hotkey.key02", &[CTRL_X], true),
+            binding("hotkey.key03", &[CTRL_X, N], true),
+            binding("hotkey.key04", &[N], false),
+            binding("hotkey.key05", &[N], true),
+        ];
+        let found: Vec<_> = find_conflicts(&bindings)
+            .into_iter()
+            .map(|c| (c.kind, c.first, c.second))
+            .collect();
+        let expected: Vec<_> = [
+            (ConflictKind::Prefix, "hotkey.key02", "hotkey.key01"),
+            (ConflictKind::Duplicate, "hotkey.key01", "hotkey.key03"),
+            (ConflictKind::Prefix, "hotkey.key02", "hotkey.key03"),
+            (ConflictKind::LocalGlobal, "hotkey.key04", "hotkey.key05"),
+        ]
+        .into_iter()
+        .map(|(k, a, b)| (k, a.to_owned(), b.to_owned()))
+        .collect();
+        assert_eq!(found, expected);
+    }
+
+    #[test]
+    fn layers_and_conditions_share_keys() {
+        let bindings = [
+            binding("hotkey.key01", &[N], true),
+            Binding {
+                layer: Some("playlist"),
+                ..binding("hotkey.key02", &[N], true)
+            },
+            Binding {
+                condition: Some("%ispaused%"),
+                ..binding("hotkey.key03", &[N], true)
+            },
+        ];
+        assert_eq!(find_conflicts(&bindings), []);
+    }
+
+    #[test]
+    fn reports_lines() {
+        let conflicts = [Conflict {
+            kind: ConflictKind::Duplicate,
+            first: "hotkey.key01".to_owned(),
+            second: "hotkey.key02".to_owned(),
+        }];
+        assert_eq!(
+            report(&conflicts),
+            "duplicate\thotkey.key01\thotkey.key02\n"
+        );
+        assert_eq!(
+            conflicts[0].to_string(),
+            "hotkey.key02 is bound to the same keys as hotkey.key01 and never runs"
+        );
+    }
+}
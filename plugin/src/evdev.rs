@@ -0,0 +1,629 @@
+use crate::{
+    backend::{Backend, Dispatch, KeyEvent, Kind},
+    keystroke::{Keystroke, Modifiers},
+    keysyms::keysym_from_str,
+};
+use std::{
+    collections::{HashMap, HashSet},
+    fs::{self, File},
+    io::{self, Read},
+    os::{fd::AsRawFd, unix::fs::OpenOptionsExt},
+    path::Path,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, Mutex,
+    },
+    thread,
+    time::Instant,
+};
+
+const EV_KEY: u16 = 0x01;
+/// How often the reader thread checks whether it should stop, in ms.
+const POLL_TIMEOUT: i32 = 200;
+
+/// `struct input_event` from `linux/input.h`.
+#[repr(C)]
+#[derive(Debug, Clone, Copy, Default)]
+struct InputEvent {
+    time: [libc::c_long; 2],
+    type_: u16,
+    code: u16,
+    value: i32,
+}
+
+/// Linux key codes for the modifier keys.
+const MODIFIER_KEYS: &[(u16, Modifiers)] = &[
+    (29, Modifiers::CONTROL), // KEY_LEFTCTRL
+    (97, Modifiers::CONTROL), // KEY_RIGHTCTRL
+    (42, Modifiers::SHIFT),   // KEY_LEFTSHIFT
+    (54, Modifiers::SHIFT),   // KEY_RIGHTSHIFT
+    (56, Modifiers::ALT),     // KEY_LEFTALT
+    (100, Modifiers::ALT),    // KEY_RIGHTALT
+    (125, Modifiers::SUPER),  // KEY_LEFTMETA
+    (126, Modifiers::SUPER),  // KEY_RIGHTMETA
+];
+
+/// Right Alt, which is AltGr on many layouts. It counts as Alt unless only the
+/// keystroke with AltGr is bound.
+const KEY_RIGHTALT: u16 = 100;
+
+/// Linux key codes and the keysym a US layout gives them without modifiers.
+const LINUX_KEYS: &[(u16, &str)] = &[
+    (1, "Escape"),
+    (2, "1"),
+    (3, "2"),
+    (4, "3"),
+    (5, "4"),
+    (6, "5"),
+    (7, "6"),
+    (8, "7"),
+    (9, "8"),
+    (10, "9"),
+    (11, "0"),
+    (12, "minus"),
+    (13, "equal"),
+    (14, "BackSpace"),
+    (15, "Tab"),
+    (16, "q"),
+    (17, "w"),
+    (18, "e"),
+    (19, "r"),
+    (20, "t"),
+    (21, "y"),
+    (22, "u"),
+    (23, "i"),
+    (24, "o"),
+    (25, "p"),
+    (26, "bracketleft"),
+    (27, "bracketright"),
+    (28, "Return"),
+    (30, "a"),
+    (31, "s"),
+    (32, "d"),
+    (33, "f"),
+    (34, "g"),
+    (35, "h"),
+    (36, "j"),
+    (37, "k"),
+    (38, "l"),
+    (39, "semicolon"),
+    (40, "apostrophe"),
+    (41, "grave"),
+    (43, "backslash"),
+    (44, "z"),
+    (45, "x"),
+    (46, "c"),
+    (47, "v"),
+    (48, "b"),
+    (49, "n"),
+    (50, "m"),
+    (51, "comma"),
+    (52, "period"),
+    (53, "slash"),
+    (55, "KP_Multiply"),
+    (57, "space"),
+    (59, "F1"),
+    (60, "F2"),
+    (61, "F3"),
+    (62, "F4"),
+    (63, "F5"),
+    (64, "F6"),
+    (65, "F7"),
+    (66, "F8"),
+    (67, "F9"),
+    (68, "F10"),
+    (71, "KP_Home"),
+    (72, "KP_Up"),
+    (73, "KP_Prior"),
+    (74, "KP_Subtract"),
+    (75, "KP_Left"),
+    (76, "KP_Begin"),
+    (77, "KP_Right"),
+    (78, "KP_Add"),
+    (79, "KP_End"),
+    (80, "KP_Down"),
+    (81, "KP_Next"),
+    (82, "KP_Insert"),
+    (83, "KP_Delete"),
+    (87, "F11"),
+    (88, "F12"),
+    (96, "KP_Enter"),
+    (98, "KP_Divide"),
+    (99, "Print"),
+    (102, "Home"),
+    (103, "Up"),
+    (104, "Prior"),
+    (105, "Left"),
+    (106, "Right"),
+    (107, "End"),
+    (108, "Down"),
+    (109, "Next"),
+    (110, "Insert"),
+    (111, "Delete"),
+    (113, "XF86AudioMute"),
+    (114, "XF86AudioLowerVolume"),
+    (115, "XF86AudioRaiseVolume"),
+    (119, "Pause"),
+    (127, "Menu"),
+    (128, "XF86Stop"),
+    (163, "XF86AudioNext"),
+    (164, "XF86AudioPlay"),
+    (165, "XF86AudioPrev"),
+    (166, "XF86AudioStop"),
+    (167, "XF86AudioRecord"),
+    (168, "XF86AudioRewind"),
+    (183, "F13"),
+    (184, "F14"),
+    (185, "F15"),
+    (186, "F16"),
+    (187, "F17"),
+    (188, "F18"),
+    (189, "F19"),
+    (190, "F20"),
+    (191, "F21"),
+    (192, "F22"),
+    (193, "F23"),
+    (194, "F24"),
+    (200, "XF86AudioPlay"),
+    (201, "XF86AudioPause"),
+    (208, "XF86AudioForward"),
+    (226, "XF86AudioMedia"),
+];
+
+/// Linux key codes and the keysym a US layout gives them with Shift, for the
+/// keys where that is another symbol rather than an upper-case letter.
+const SHIFTED_LINUX_KEYS: &[(u16, &str)] = &[
+    (2, "exclam"),
+    (3, "at"),
+    (4, "numbersign"),
+    (5, "dollar"),
+    (6, "percent"),
+    (7, "asciicircum"),
+    (8, "ampersand"),
+    (9, "asterisk"),
+    (10, "parenleft"),
+    (11, "parenright"),
+    (12, "underscore"),
+    (13, "plus"),
+    (26, "braceleft"),
+    (27, "braceright"),
+    (39, "colon"),
+    (40, "quotedbl"),
+    (41, "asciitilde"),
+    (43, "bar"),
+    (51, "less"),
+    (52, "greater"),
+    (53, "question"),
+];
+
+/// Turns key events into keystrokes, tracking the modifier state itself since
+/// there is no X server or compositor doing it.
+struct KeyState {
+    keysyms: HashMap<u16, i32>,
+    shifted: HashMap<u16, i32>,
+    /// Bound keystrokes, shared with the backend so they can be replaced.
+    keys: Arc<Mutex<HashSet<Keystroke>>>,
+    /// Modifier keys currently held down, by key code.
+    held: HashSet<u16>,
+    /// Other keys held down whose press was reported, by key code.
+    down: HashMap<u16, Keystroke>,
+    /// Until when every key is passed on, set while a key sequence is pending.
+    deadline: Option<Instant>,
+}
+
+impl KeyState {
+    fn new(keys: &[Keystroke]) -> Self {
+        Self {
+            keysyms: LINUX_KEYS
+                .iter()
+                .filter_map(|(code, name)| Some((*code, keysym_from_str(name)?)))
+                .collect(),
+            shifted: SHIFTED_LINUX_KEYS
+                .iter()
+                .filter_map(|(code, name)| Some((*code, keysym_from_str(name)?)))
+                .collect(),
+            keys: Arc::new(Mutex::new(keys.iter().copied().collect())),
+            held: HashSet::new(),
+            down: HashMap::new(),
+            deadline: None,
+        }
+    }
+
+    fn modifiers(&self) -> Modifiers {
+        MODIFIER_KEYS
+            .iter()
+            .filter(|(code, _)| self.held.contains(code))
+            .fold(Modifiers::empty(), |mods, (_, m)| mods | *m)
+    }
+
+    /// The modifiers with Right Alt taken as AltGr, if it is held.
+    fn alt_gr_modifiers(&self) -> Option<Modifiers> {
+        if !self.held.contains(&KEY_RIGHTALT) {
+            return None;
+        }
+        let mods = MODIFIER_KEYS
+            .iter()
+            .filter(|(code, _)| *code != KEY_RIGHTALT && self.held.contains(code))
+            .fold(Modifiers::empty(), |mods, (_, m)| mods | *m);
+        Some(mods | Modifiers::ALT_GR)
+    }
+
+    /// Like the X11 backend, a key that gives another symbol with Shift is
+    /// reported as that symbol without Shift, `exclam` rather than `Shift 1`,
+    /// while letters stay `Shift a`.
+    fn keystroke(&self, code: u16, keysym: i32, mods: Modifiers) -> Keystroke {
+        match self.shifted.get(&code) {
+            Some(&shifted) if mods.contains(Modifiers::SHIFT) => {
+                Keystroke::new(shifted, mods & !Modifiers::SHIFT)
+            }
+            _ => Keystroke::new(keysym, mods),
+        }
+    }
+
+    /// Returns the bound keystroke a key press completes, if any, or any
+    /// keystroke while a key sequence is pending. Releases are reported for
+    /// the keys whose press was.
+    fn feed(&mut self, event: &InputEvent) -> Option<(Keystroke, KeyEvent)> {
+        if event.type_ != EV_KEY {
+            return None;
+        }
+
+        if MODIFIER_KEYS.iter().any(|(code, _)| *code == event.code) {
+            match event.value {
+                0 => self.held.remove(&event.code),
+                _ => self.held.insert(event.code),
+            };
+            return None;
+        }
+
+        match event.value {
+            1 => {}
+            0 => {
+                let keystroke = self.down.remove(&event.code)?;
+                return Some((keystroke, KeyEvent::Release));
+            }
+            // Autorepeat
+            _ => return None,
+        }
+
+        let keysym = *self.keysyms.get(&event.code)?;
+        let mut keystrokes = [Some(self.modifiers()), self.alt_gr_modifiers()]
+            .into_iter()
+            .flatten()
+            .map(|mods| self.keystroke(event.code, keysym, mods));
+        let pending = self.deadline.is_some_and(|d| Instant::now() < d);
+        let keystroke = if pending {
+            keystrokes.next()?
+        } else {
+            let keys = self.keys.lock().ok()?;
+            keystrokes.find(|k| keys.contains(k))?
+        };
+        self.down.insert(event.code, keystroke);
+        Some((keystroke, KeyEvent::Press))
+    }
+}
+
+/// Reads whole events from a device or a recording, `None` at the end.
+fn read_event(reader: &mut impl Read) -> io::Result<Option<InputEvent>> {
+    let mut buf = [0u8; std::mem::size_of::<InputEvent>()];
+    match reader.read_exact(&mut buf) {
+        Ok(()) => Ok(Some(unsafe {
+            std::ptr::read_unaligned(buf.as_ptr() as *const InputEvent)
+        })),
+        Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => Ok(None),
+        Err(e) => Err(e),
+    }
+}
+
+/// Feeds a recorded stream of `input_event`s through the same path as the
+/// devices, e.g. one captured with `cat /dev/input/eventN > file`.
+#[cfg(test)]
+fn replay(path: impl AsRef<Path>, keys: &[Keystroke], dispatch: &Dispatch) -> io::Result<()> {
+    let mut file = File::open(path)?;
+    let mut state = KeyState::new(keys);
+    while let Some(event) = read_event(&mut file)? {
+        if let Some((keystroke, key_event)) = state.feed(&event) {
+            state.deadline = dispatch(keystroke, key_event);
+        }
+    }
+    Ok(())
+}
+
+/// Global hotkeys read straight from `/dev/input/event*`, for setups with
+/// neither a portal nor an X server. Needs read access to the devices,
+/// usually through the `input` group.
+pub struct EvdevBackend {
+    stop: Arc<AtomicBool>,
+    keys: Arc<Mutex<HashSet<Keystroke>>>,
+    handle: thread::JoinHandle<()>,
+}
+
+impl EvdevBackend {
+    pub fn new(keys: &[Keystroke], dispatch: Dispatch) -> io::Result<Self> {
+        let devices = open_devices(Path::new("/dev/input"))?;
+        if devices.is_empty() {
+            return Err(io::Error::new(
+                io::ErrorKind::PermissionDenied,
+                "no readable input devices",
+            ));
+        }
+
+        let stop = Arc::new(AtomicBool::new(false));
+        let thread_stop = stop.clone();
+        let state = KeyState::new(keys);
+        let keys = state.keys.clone();
+        let handle = thread::spawn(move || {
+            if let Err(e) = event_loop(devices, state, &dispatch, &thread_stop) {
+                tracing::error!("evdev event loop failed: {e}");
+            }
+        });
+
+        Ok(Self { stop, keys, handle })
+    }
+}
+
+impl Backend for EvdevBackend {
+    fn kind(&self) -> Kind {
+        Kind::Evdev
+    }
+
+    fn set_keys(&self, keys: &[Keystroke]) {
+        if let Ok(mut k) = self.keys.lock() {
+            *k = keys.iter().copied().collect();
+        }
+    }
+
+    fn stop(self: Box<Self>) {
+        self.stop.store(true, Ordering::Relaxed);
+        if self.handle.join().is_err() {
+            tracing::error!("evdev event loop panicked");
+        }
+    }
+}
+
+fn open_devices(dir: &Path) -> io::Result<Vec<File>> {
+    let mut devices = Vec::new();
+    for entry in fs::read_dir(dir)? {
+        let path = entry?.path();
+        let is_event_device = path
+            .file_name()
+            .and_then(|name| name.to_str())
+            .is_some_and(|name| name.starts_with("event"));
+        if !is_event_device {
+            continue;
+        }
+
+        match File::options()
+            .read(true)
+            .custom_flags(libc::O_NONBLOCK)
+            .open(&path)
+        {
+            Ok(file) => devices.push(file),
+            Err(e) => tracing::debug!("Skipping {}: {e}", path.display()),
+        }
+    }
+    Ok(devices)
+}
+
+fn event_loop(
+    mut devices: Vec<File>,
+    mut state: KeyState,
+    dispatch: &Dispatch,
+    stop: &AtomicBool,
+) -> io::Result<()> {
+    while !stop.load(Ordering::Relaxed) && !devices.is_empty() {
+        let mut fds: Vec<libc::pollfd> = devices
+            .iter()
+            .map(|device| libc::pollfd {
+                fd: device.as_raw_fd(),
+                events: libc::POLLIN,
+                revents: 0,
+            })
+            .collect();
+
+        let ready =
+            unsafe { libc::poll(fds.as_mut_ptr(), fds.len() as libc::nfds_t, POLL_TIMEOUT) };
+        if ready < 0 {
+            let e = io::Error::last_os_error();
+            if e.kind() == io::ErrorKind::Interrupted {
+                continue;
+            }
+            return Err(e);
+        }
+
+        let mut unplugged = Vec::new();
+        for (i, pollfd) in fds.iter().enumerate() {
+            if pollfd.revents & (libc::POLLERR | libc::POLLHUP | libc::POLLNVAL) != 0 {
+                unplugged.push(i);
+                continue;
+            }
+            if pollfd.revents & libc::POLLIN == 0 {
+                continue;
+            }
+
+            loop {
+                match read_event(&mut devices[i]) {
+                    Ok(Some(event)) => {
+                        if let Some((keystroke, key_event)) = state.feed(&event) {
+                            state.deadline = dispatch(keystroke, key_event);
+                        }
+                    }
+                    Ok(None) => break,
+                    Err(e) if e.kind() == io::ErrorKind::WouldBlock => break,
+                    Err(e) => {
+                        tracing::debug!("Dropping input device: {e}");
+                        unplugged.push(i);
+                        break;
+                    }
+                }
+            }
+        }
+
+        for i in unplugged.into_iter().rev() {
+            devices.remove(i);
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Mutex;
+
+    fn key(code: u16, value: i32) -> InputEvent {
+        InputEvent {
+            type_: EV_KEY,
+            code,
+            value,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn every_linux_key_has_a_keysym() {
+        for (code, name) in LINUX_KEYS.iter().chain(SHIFTED_LINUX_KEYS) {
+            assert!(keysym_from_str(name).is_some(), "key {code}: {name}");
+        }
+    }
+
+    #[test]
+    fn tracks_modifiers_across_both_sides() {
+        let ctrl_n = Keystroke::new(0x6e, Modifiers::CONTROL);
+        let mut state = KeyState::new(&[ctrl_n]);
+
+        assert_eq!(state.feed(&key(49, 1)), None);
+        assert_eq!(state.feed(&key(49, 0)), None);
+
+        state.feed(&key(29, 1));
+        state.feed(&key(97, 1));
+        state.feed(&key(29, 0));
+        // Right Ctrl is still held
+        assert_eq!(state.feed(&key(49, 1)), Some((ctrl_n, KeyEvent::Press)));
+        assert_eq!(state.feed(&key(49, 2)), None);
+        assert_eq!(state.feed(&key(49, 0)), Some((ctrl_n, KeyEvent::Release)));
+
+        state.feed(&key(97, 0));
+        assert_eq!(state.feed(&key(49, 1)), None);
+    }
+
+    #[test]
+    fn right_alt_is_alt_gr_when_only_that_is_bound() {
+        let (alt_n, alt_gr_x) = (
+            Keystroke::new(0x6e, Modifiers::ALT),
+            Keystroke::new(0x78, Modifiers::ALT_GR),
+        );
+        let mut state = KeyState::new(&[alt_n, alt_gr_x]);
+
+        state.feed(&key(100, 1));
+        assert_eq!(state.feed(&key(49, 1)), Some((alt_n, KeyEvent::Press)));
+        assert_eq!(state.feed(&key(45, 1)), Some((alt_gr_x, KeyEvent::Press)));
+        state.feed(&key(100, 0));
+        state.feed(&key(56, 1));
+        assert_eq!(state.feed(&key(45, 1)), None);
+    }
+
+    #[test]
+    fn ignores_other_event_types() {
+        let n = Keystroke::from_raw(0x6e, 0);
+        let mut state = KeyState::new(&[n]);
+        let syn = InputEvent {
+            type_: 0,
+            code: 49,
+            value: 1,
+            ..Default::default()
+        };
+        assert_eq!(state.feed(&syn), None);
+        assert_eq!(state.feed(&key(49, 1)), Some((n, KeyEvent::Press)));
+    }
+
+    #[test]
+    fn releases_keep_the_modifiers_of_the_press() {
+        let ctrl_n = Keystroke::new(0x6e, Modifiers::CONTROL);
+        let mut state = KeyState::new(&[ctrl_n]);
+
+        state.feed(&key(29, 1));
+        assert_eq!(state.feed(&key(49, 1)), Some((ctrl_n, KeyEvent::Press)));
+        state.feed(&key(29, 0));
+        assert_eq!(state.feed(&key(49, 0)), Some((ctrl_n, KeyEvent::Release)));
+        assert_eq!(state.feed(&key(49, 0)), None);
+    }
+
+    #[test]
+    fn passes_on_every_key_during_a_sequence() {
+        let ctrl_x = Keystroke::new(0x78, Modifiers::CONTROL);
+        let mut state = KeyState::new(&[ctrl_x]);
+
+        state.feed(&key(29, 1));
+        assert_eq!(state.feed(&key(45, 1)), Some((ctrl_x, KeyEvent::Press)));
+        state.feed(&key(29, 0));
+        assert_eq!(state.feed(&key(49, 1)), None);
+
+        state.deadline = Some(Instant::now() + std::time::Duration::from_secs(60));
+        assert_eq!(
+            state.feed(&key(49, 1)),
+            Some((Keystroke::from_raw(0x6e, 0), KeyEvent::Press))
+        );
+        // Not the modifiers on their own
+        assert_eq!(state.feed(&key(42, 1)), None);
+        assert_eq!(
+            state.feed(&key(49, 1)),
+            Some((Keystroke::new(0x6e, Modifiers::SHIFT), KeyEvent::Press))
+        );
+
+        state.deadline = Some(Instant::now());
+        assert_eq!(state.feed(&key(49, 1)), None);
+    }
+
+    #[test]
+    fn shifted_symbols_are_named_like_x11() {
+        let (exclam, ctrl_question, shift_n) = (
+            Keystroke::from_raw(0x21, 0),
+            Keystroke::new(0x3f, Modifiers::CONTROL),
+            Keystroke::new(0x6e, Modifiers::SHIFT),
+        );
+        let mut state = KeyState::new(&[exclam, ctrl_question, shift_n]);
+
+        state.feed(&key(42, 1));
+        assert_eq!(state.feed(&key(2, 1)), Some((exclam, KeyEvent::Press)));
+        assert_eq!(state.feed(&key(49, 1)), Some((shift_n, KeyEvent::Press)));
+        state.feed(&key(54, 1));
+        state.feed(&key(42, 0));
+        state.feed(&key(97, 1));
+        assert_eq!(
+            state.feed(&key(53, 1)),
+            Some((ctrl_question, KeyEvent::Press))
+        );
+        state.feed(&key(54, 0));
+        assert_eq!(state.feed(&key(2, 1)), None);
+    }
+
+    /// Ctrl+Alt+n, XF86AudioPlay and a plain n captured from a keyboard,
+    /// including the EV_MSC and EV_SYN events around each key.
+    #[test]
+    #[cfg(target_pointer_width = "64")]
+    fn replays_recorded_stream() {
+        let path = concat!(env!("CARGO_MANIFEST_DIR"), "/testdata/ctrl_alt_n.events");
+        let keys = [
+            Keystroke::new(0x6e, Modifiers::CONTROL | Modifiers::ALT),
+            Keystroke::from_raw(0x1008FF14, 0),
+        ];
+
+        let seen = Arc::new(Mutex::new(Vec::new()));
+        let sink = seen.clone();
+        let dispatch: Dispatch = Arc::new(move |keystroke, key_event| {
+            sink.lock().unwrap().push((keystroke, key_event));
+            None
+        });
+
+        replay(path, &keys, &dispatch).expect("replay");
+        let seen = seen.lock().unwrap();
+        let presses: Vec<_> = seen
+            .iter()
+            .filter(|(_, e)| *e == KeyEvent::Press)
+            .map(|(k, _)| *k)
+            .collect();
+        assert_eq!(presses, keys);
+        assert_eq!(seen.len(), keys.len() * 2);
+    }
+}
@@ -0,0 +1,234 @@
+use crate::{
+    actions::bound_actions,
+    utils::{escape_title, log_to_deadbeef, percent_decode},
+};
+use deadbeef_sys::{DeadBeef, PlItem, TitleFormat, DDB_LOG_LAYER_DEFAULT, DDB_LOG_LAYER_INFO};
+use std::process::Stdio;
+
+/// Config key to allow bindings running external commands, off by default so
+/// a config line can't run programs unless the user opted in.
+pub const CONF_COMMANDS_ENABLED: &str = "hotkeys.commands_enabled";
+
+const NAME: &str = "hotkeys_exec";
+
+/// Parses the action of a binding line running an external command, like
+/// `hotkeys_exec:wl-copy,%25artist%25%20-%20%25title%25`. The program and its
+/// arguments follow a colon, separated by commas and with spaces, `%`, `;`,
+/// `,` and `"` written as `%XX` escapes, so the action name stays a single
+/// token in the config line.
+///
+/// Arguments with a `%` are title formatted for the playing track before
+/// running the command, without a shell. The program and the other arguments
+/// are used as written, so shell scripts can take fields as arguments:
+/// `hotkeys_exec:sh,-c,echo%20$1,_,%25path%25`.
+///
+/// Returns `None` for other actions.
+pub fn parse_command(action: &str) -> Option<Result<Vec<String>, String>> {
+    let rest = action.trim().strip_prefix(NAME)?;
+    let params = match rest.strip_prefix(':') {
+        Some(params) => params,
+        None if rest.is_empty() => return Some(Err(format!("{NAME}: missing command"))),
+        None => return None,
+    };
+    if params.contains(char::is_whitespace) {
+        return Some(Err(format!("{action}: spaces must be written as %20")));
+    }
+    let args = params
+        .split(',')
+        .map(|arg| percent_decode(arg).map_err(|e| format!("{NAME}: {e}")))
+        .collect::<Result<Vec<_>, _>>()
+        .and_then(|args| {
+            if args[0].is_empty() {
+                Err(format!("{NAME}: missing command"))
+            } else {
+                Ok(args)
+            }
+        });
+    Some(args)
+}
+
+/// The command actions to offer, see [`bound_actions`].
+pub fn command_actions<'a>(bound: impl IntoIterator<Item = &'a str>) -> Vec<(String, String)> {
+    bound_actions(bound, |name| {
+        let args = parse_command(name)?.ok()?;
+        Some(format!("Hotkeys/Run {}", escape_title(&args.join(" "))))
+    })
+}
+
+pub fn commands_enabled() -> bool {
+    DeadBeef::conf_get_int(CONF_COMMANDS_ENABLED, 0) != 0
+}
+
+/// Formats the arguments for the playing track and starts the command,
+/// logging its output once it exits.
+pub fn run_command(args: &[String]) {
+    // Reported when the config is read, not on every press
+    if !commands_enabled() {
+        tracing::debug!("Not running {:?}, commands are disabled", args[0]);
+        return;
+    }
+
+    let it = DeadBeef::streamer_get_playing_track();
+    let plt = it.as_ref().and_then(PlItem::playlist);
+    let args = format_args(args, |arg| {
+        TitleFormat::compile(arg)
+            .and_then(|tf| tf.eval(it.as_ref(), plt.as_ref()))
+            .map_err(|e| format!("{e} in \"{arg}\""))
+    });
+    let args = match args {
+        Ok(args) => args,
+        Err(e) => {
+            log_to_deadbeef(DDB_LOG_LAYER_DEFAULT, &e);
+            return;
+        }
+    };
+
+    let child = smol::process::Command::new(&args[0])
+        .args(&args[1..])
+        .stdin(Stdio::null())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn();
+    let child = match child {
+        Ok(child) => child,
+        Err(e) => {
+            log_to_deadbeef(
+                DDB_LOG_LAYER_DEFAULT,
+                &format!("unable to run {:?}: {e}", args[0]),
+            );
+            return;
+        }
+    };
+
+    smol::spawn(async move {
+        let output = match child.output().await {
+            Ok(output) => output,
+            Err(e) => {
+                log_to_deadbeef(DDB_LOG_LAYER_DEFAULT, &format!("{:?}: {e}", args[0]));
+                return;
+            }
+        };
+        let mut msg = format!("{:?} {}", args[0], output.status);
+        for out in [&output.stdout, &output.stderr] {
+            let out = String::from_utf8_lossy(out);
+            if !out.trim().is_empty() {
+                msg.push('\n');
+                msg.push_str(out.trim_end());
+            }
+        }
+        let layer = if output.status.success() {
+            DDB_LOG_LAYER_INFO
+        } else {
+            DDB_LOG_LAYER_DEFAULT
+        };
+        log_to_deadbeef(layer, &msg);
+    })
+    .detach();
+}
+
+/// Title formats the arguments after the program that contain a `%`, leaving
+/// the rest alone since `$`, `'` and brackets are title format syntax too.
+fn format_args(
+    args: &[String],
+    eval: impl Fn(&str) -> Result<String, String>,
+) -> Result<Vec<String>, String> {
+    let (program, args) = args.split_first().ok_or("missing command")?;
+    std::iter::once(Ok(program.clone()))
+        .chain(args.iter().map(|arg| {
+            if arg.contains('%') {
+                eval(arg)
+            } else {
+                Ok(arg.clone())
+            }
+        }))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_commands() {
+        assert_eq!(
+            parse_command("hotkeys_exec:wl-copy,%25artist%25%20-%20%25title%25"),
+            Some(Ok(vec![
+                "wl-copy".to_owned(),
+                "%artist% - %title%".to_owned()
+            ]))
+        );
+        assert_eq!(
+            parse_command("hotkeys_exec:sh,-c,echo%20%22$1%22%20>>%20~/played,_,%25path%25"),
+            Some(Ok(vec![
+                "sh".to_owned(),
+                "-c".to_owned(),
+                r#"echo "$1" >> ~/played"#.to_owned(),
+                "_".to_owned(),
+                "%path%".to_owned(),
+            ]))
+        );
+        assert_eq!(
+            parse_command(r"hotkeys_exec:notify-send,,'a\b',a%2Cb"),
+            Some(Ok(vec![
+                "notify-send".to_owned(),
+                String::new(),
+                r"'a\b'".to_owned(),
+                "a,b".to_owned(),
+            ]))
+        );
+        for action in [
+            "hotkeys_exec",
+            "hotkeys_exec:",
+            "hotkeys_exec:,x",
+            "hotkeys_exec:x,%2",
+            // Spaces would be squeezed when the line is read
+            "hotkeys_exec:notify-send  x",
+        ] {
+            assert!(matches!(parse_command(action), Some(Err(_))), "{action:?}");
+        }
+        assert_eq!(parse_command("hotkeys_execute:x"), None);
+        assert_eq!(parse_command("stop"), None);
+    }
+
+    #[test]
+    fn formats_only_arguments_with_fields() {
+        let eval = |arg: &str| Ok(arg.replace("%path%", "/music/a b.flac"));
+        let args = parse_command("hotkeys_exec:sh,-c,echo%20%22$1%22%20>>%20~/played,_,%25path%25")
+            .unwrap()
+            .unwrap();
+        assert_eq!(
+            format_args(&args, eval),
+            Ok(vec![
+                "sh".to_owned(),
+                "-c".to_owned(),
+                r#"echo "$1" >> ~/played"#.to_owned(),
+                "_".to_owned(),
+                "/music/a b.flac".to_owned(),
+            ])
+        );
+
+        // Not even the program is formatted
+        let args = ["%cmd%".to_owned(), "'a\\b'".to_owned(), "[%x%]".to_owned()];
+        let formatted = format_args(&args, |arg| Ok(format!("<{arg}>"))).unwrap();
+        assert_eq!(formatted, ["%cmd%", "'a\\b'", "<[%x%]>"]);
+
+        let failing = format_args(&args, |arg| Err(format!("bad {arg}")));
+        assert_eq!(failing, Err("bad [%x%]".to_owned()));
+        assert!(format_args(&[], eval).is_err());
+    }
+
+    #[test]
+    fn titles_commands() {
+        assert_eq!(
+            command_actions([
+                "stop",
+                "hotkeys_exec:tee,-a,/tmp/x",
+                "hotkeys_exec:tee,-a,/tmp/x"
+            ]),
+            [(
+                "hotkeys_exec:tee,-a,/tmp/x".to_owned(),
+                "Hotkeys/Run tee -a \\/tmp\\/x".to_owned()
+            )]
+        );
+    }
+}
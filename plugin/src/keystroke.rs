@@ -0,0 +1,510 @@
+use crate::keysyms::{keysym_from_str, name_from_keysym, similar_key_names};
+use std::{
+    fmt,
+    ops::{BitAnd, BitOr, BitOrAssign, Not},
+    str::FromStr,
+};
+
+/// Modifier keys held with a key, as the X11 modifier mask DeadBeef uses.
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Default, PartialOrd, Ord)]
+pub struct Modifiers(i32);
+
+impl Modifiers {
+    pub const SHIFT: Self = Self(1 << 0);
+    pub const LOCK: Self = Self(1 << 1);
+    pub const CONTROL: Self = Self(1 << 2);
+    /// Mod1
+    pub const ALT: Self = Self(1 << 3);
+    /// Mod2
+    pub const NUM_LOCK: Self = Self(1 << 4);
+    /// Mod3, where Hyper keys are usually put when it's kept apart from Super.
+    pub const HYPER: Self = Self(1 << 5);
+    /// Mod4
+    pub const SUPER: Self = Self(1 << 6);
+    /// Mod5, ISO_Level3_Shift on layouts with an AltGr key.
+    pub const ALT_GR: Self = Self(1 << 7);
+    /// Lock states rather than held keys, ignored when matching bindings.
+    pub const LOCKS: Self = Self(Self::LOCK.0 | Self::NUM_LOCK.0);
+
+    pub const fn empty() -> Self {
+        Self(0)
+    }
+
+    /// Keeps unknown bits, so masks from X11 or DeadBeef pass through as is.
+    pub const fn from_bits(bits: i32) -> Self {
+        Self(bits)
+    }
+
+    pub const fn bits(self) -> i32 {
+        self.0
+    }
+
+    pub const fn is_empty(self) -> bool {
+        self.0 == 0
+    }
+
+    pub const fn contains(self, other: Self) -> bool {
+        self.0 & other.0 == other.0
+    }
+}
+
+impl BitOr for Modifiers {
+    type Output = Self;
+
+    fn bitor(self, rhs: Self) -> Self {
+        Self(self.0 | rhs.0)
+    }
+}
+
+impl BitOrAssign for Modifiers {
+    fn bitor_assign(&mut self, rhs: Self) {
+        self.0 |= rhs.0;
+    }
+}
+
+impl BitAnd for Modifiers {
+    type Output = Self;
+
+    fn bitand(self, rhs: Self) -> Self {
+        Self(self.0 & rhs.0)
+    }
+}
+
+impl Not for Modifiers {
+    type Output = Self;
+
+    fn not(self) -> Self {
+        Self(!self.0)
+    }
+}
+
+/// Modifier names in DeadBeef keystrokes, in the order its preferences write them.
+pub const MODIFIER_NAMES: &[(Modifiers, &str)] = &[
+    (Modifiers::SHIFT, "Shift"),
+    (Modifiers::CONTROL, "Ctrl"),
+    (Modifiers::SUPER, "Super"),
+    (Modifiers::ALT, "Alt"),
+    (Modifiers::HYPER, "Hyper"),
+    (Modifiers::ALT_GR, "AltGr"),
+];
+
+/// Other names accepted for modifiers. Meta is the Super key, as on KDE.
+const MODIFIER_ALIASES: &[(Modifiers, &str)] = &[
+    (Modifiers::SUPER, "Meta"),
+    (Modifiers::ALT, "Mod1"),
+    (Modifiers::HYPER, "Mod3"),
+    (Modifiers::SUPER, "Mod4"),
+    (Modifiers::ALT_GR, "Mod5"),
+];
+
+impl fmt::Debug for Modifiers {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut names: Vec<String> = MODIFIER_NAMES
+            .iter()
+            .chain(&[(Modifiers::LOCK, "Lock"), (Modifiers::NUM_LOCK, "NumLock")])
+            .filter(|(m, _)| self.contains(*m))
+            .map(|(_, name)| name.to_string())
+            .collect();
+        let known = MODIFIER_NAMES
+            .iter()
+            .fold(Modifiers::LOCK | Modifiers::NUM_LOCK, |all, (m, _)| {
+                all | *m
+            });
+        let unknown = *self & !known;
+        if !unknown.is_empty() {
+            names.push(format!("{:#x}", unknown.0));
+        }
+        write!(f, "Modifiers({})", names.join(" | "))
+    }
+}
+
+/// A key with its modifiers, written `Ctrl Alt Left` in DeadBeef's config.
+#[derive(Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct Keystroke {
+    pub keysym: i32,
+    pub modifiers: Modifiers,
+}
+
+impl Keystroke {
+    pub const fn new(keysym: i32, modifiers: Modifiers) -> Self {
+        Self { keysym, modifiers }
+    }
+
+    /// From the key and modifier mask DeadBeef and X11 pass around.
+    pub const fn from_raw(keysym: i32, modifiers: i32) -> Self {
+        Self::new(keysym, Modifiers::from_bits(modifiers))
+    }
+
+    /// The form bindings are matched in: an upper case letter is the lower
+    /// case one with Shift, as backends report it, and Lock and NumLock are
+    /// left out. With Caps Lock on, only the Shift bit tells whether Shift is
+    /// held.
+    pub fn normalized(self) -> Self {
+        let caps_lock = self.modifiers.contains(Modifiers::LOCK);
+        let mut modifiers = self.modifiers & !Modifiers::LOCKS;
+        let keysym = match self.keysym {
+            // A-Z, and Latin-1 À-Þ except ×
+            0x41..=0x5a | 0xc0..=0xde if self.keysym != 0xd7 => {
+                if !caps_lock {
+                    modifiers |= Modifiers::SHIFT;
+                }
+                self.keysym + 0x20
+            }
+            keysym => keysym,
+        };
+        Self::new(keysym, modifiers)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ParseErrorKind {
+    /// Neither a modifier nor a key name.
+    UnknownKey,
+    /// A hex keysym that doesn't parse or is 0.
+    InvalidKeysym,
+    /// Only modifiers, or nothing at all.
+    MissingKey,
+}
+
+/// Why a keystroke didn't parse, pointing at the offending token.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ParseError {
+    pub kind: ParseErrorKind,
+    /// The token as written, the whole keystroke for [`ParseErrorKind::MissingKey`].
+    pub token: String,
+    /// Byte offset of the token in the parsed string.
+    pub offset: usize,
+    /// Key names the token may have been meant as, best first.
+    pub suggestions: Vec<&'static str>,
+}
+
+impl ParseError {
+    fn new(kind: ParseErrorKind, token: &str, offset: usize) -> Self {
+        let suggestions = match kind {
+            ParseErrorKind::UnknownKey => similar_key_names(token),
+            _ => Vec::new(),
+        };
+        Self {
+            kind,
+            token: token.to_owned(),
+            offset,
+            suggestions,
+        }
+    }
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let Self { token, offset, .. } = self;
+        match self.kind {
+            ParseErrorKind::UnknownKey => write!(f, "unknown key \"{token}\" at byte {offset}")?,
+            ParseErrorKind::InvalidKeysym => {
+                write!(f, "invalid keysym \"{token}\" at byte {offset}")?
+            }
+            ParseErrorKind::MissingKey => write!(f, "no key in \"{token}\" at byte {offset}")?,
+        }
+        if let Some((last, rest)) = self.suggestions.split_last() {
+            write!(f, ", did you mean ")?;
+            if !rest.is_empty() {
+                write!(f, "{} or ", rest.join(", "))?;
+            }
+            write!(f, "{last}?")?;
+        }
+        Ok(())
+    }
+}
+
+impl std::error::Error for ParseError {}
+
+impl FromStr for Keystroke {
+    type Err = ParseError;
+
+    /// Parses modifier and key names separated by spaces, like DeadBeef does.
+    /// Keys are keysym names or hex keysyms like `0xff51`.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut keysym = None;
+        let mut modifiers = Modifiers::empty();
+
+        for token in s.split_whitespace() {
+            let offset = token.as_ptr() as usize - s.as_ptr() as usize;
+            if let Some((m, _)) = MODIFIER_NAMES
+                .iter()
+                .chain(MODIFIER_ALIASES)
+                .find(|(_, name)| name.eq_ignore_ascii_case(token))
+            {
+                modifiers |= *m;
+                continue;
+            }
+
+            let lower = token.to_ascii_lowercase();
+            let sym = match lower.strip_prefix("0x") {
+                Some(hex) => i32::from_str_radix(hex, 16)
+                    .ok()
+                    .filter(|&k| k != 0)
+                    .ok_or_else(|| ParseError::new(ParseErrorKind::InvalidKeysym, token, offset))?,
+                None => keysym_from_str(token)
+                    .ok_or_else(|| ParseError::new(ParseErrorKind::UnknownKey, token, offset))?,
+            };
+            keysym = Some(sym);
+        }
+
+        let keysym = keysym.ok_or_else(|| {
+            let token = s.trim();
+            let offset = s.len() - s.trim_start().len();
+            ParseError::new(ParseErrorKind::MissingKey, token, offset)
+        })?;
+        Ok(Self::new(keysym, modifiers))
+    }
+}
+
+impl fmt::Display for Keystroke {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (m, name) in MODIFIER_NAMES {
+            if self.modifiers.contains(*m) {
+                write!(f, "{name} ")?;
+            }
+        }
+        match name_from_keysym(self.keysym).and_then(|n| n.to_str().ok()) {
+            Some(name) => f.write_str(name),
+            None => write!(f, "{:#x}", self.keysym),
+        }
+    }
+}
+
+impl fmt::Debug for Keystroke {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Keystroke({self}")?;
+        let shown = MODIFIER_NAMES
+            .iter()
+            .fold(Modifiers::empty(), |a, (m, _)| a | *m);
+        if !(self.modifiers & !shown).is_empty() {
+            write!(f, ", {:?}", self.modifiers)?;
+        }
+        write!(f, ")")
+    }
+}
+
+/// Parses a key sequence like `Ctrl x, n`, one keystroke per comma separated
+/// part, normalized for matching. A plain keystroke is a sequence of one.
+///
+/// Error offsets are into the whole sequence.
+pub fn parse_sequence(s: &str) -> Result<Vec<Keystroke>, ParseError> {
+    let mut start = 0;
+    s.split(',')
+        .map(|part| {
+            let offset = start;
+            start += part.len() + 1;
+            part.parse()
+                .map(Keystroke::normalized)
+                .map_err(|e| ParseError {
+                    offset: e.offset + offset,
+                    ..e
+                })
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::keysyms::KEY_TABLE;
+    use proptest::prelude::*;
+
+    fn modifiers(bits: u8) -> Modifiers {
+        MODIFIER_NAMES
+            .iter()
+            .enumerate()
+            .filter(|(i, _)| bits & (1 << i) != 0)
+            .fold(Modifiers::empty(), |all, (_, (m, _))| all | *m)
+    }
+
+    #[test]
+    fn parses_keystrokes() {
+        let ctrl_alt = Modifiers::CONTROL | Modifiers::ALT;
+        assert_eq!(
+            "Ctrl Alt Left".parse(),
+            Ok(Keystroke::new(0xff51, ctrl_alt))
+        );
+        assert_eq!(
+            "alt  CTRL 0xff51".parse(),
+            Ok(Keystroke::new(0xff51, ctrl_alt))
+        );
+        assert_eq!("A".parse(), Ok(Keystroke::from_raw(0x41, 0)));
+        for s in ["", "Ctrl", "NoSuchKey", "Ctrl NoSuchKey", "0xzz", "0x0"] {
+            assert!(s.parse::<Keystroke>().is_err(), "{s:?}");
+        }
+    }
+
+    #[test]
+    fn parses_extra_modifiers() {
+        let table = [
+            ("Hyper x", Modifiers::HYPER),
+            ("Mod3 x", Modifiers::HYPER),
+            ("AltGr x", Modifiers::ALT_GR),
+            ("mod5 x", Modifiers::ALT_GR),
+            ("Meta x", Modifiers::SUPER),
+            ("Mod1 Mod4 x", Modifiers::ALT | Modifiers::SUPER),
+        ];
+        for (s, modifiers) in table {
+            assert_eq!(s.parse(), Ok(Keystroke::new(0x78, modifiers)), "{s:?}");
+        }
+        let keystroke = Keystroke::new(0x78, Modifiers::HYPER | Modifiers::ALT_GR);
+        assert_eq!(keystroke.to_string(), "Hyper AltGr x");
+    }
+
+    #[test]
+    fn formats_like_deadbeef() {
+        let all = Modifiers::SHIFT | Modifiers::CONTROL | Modifiers::SUPER | Modifiers::ALT;
+        assert_eq!(
+            Keystroke::new(0xffff, all).to_string(),
+            "Shift Ctrl Super Alt Delete"
+        );
+        assert_eq!(
+            Keystroke::from_raw(0x7fff_fff0, 0).to_string(),
+            "0x7ffffff0"
+        );
+        assert_eq!(
+            format!("{:?}", Keystroke::from_raw(0x61, (1 << 2) | (1 << 4))),
+            "Keystroke(Ctrl a, Modifiers(Ctrl | NumLock))"
+        );
+    }
+
+    #[test]
+    fn normalizes_upper_case_letters() {
+        let shift_a = Keystroke::new(0x61, Modifiers::SHIFT);
+        assert_eq!(Keystroke::from_raw(0x41, 0).normalized(), shift_a);
+        assert_eq!(shift_a.normalized(), shift_a);
+        assert_eq!(Keystroke::from_raw(0xc4, 0).normalized().keysym, 0xe4);
+        assert_eq!(Keystroke::from_raw(0xd7, 0).normalized().keysym, 0xd7);
+        assert_eq!(Keystroke::from_raw(0x31, 0).normalized().keysym, 0x31);
+    }
+
+    #[test]
+    fn normalizing_ignores_lock_states() {
+        let (a, shift_a) = (
+            Keystroke::from_raw(0x61, 0),
+            Keystroke::new(0x61, Modifiers::SHIFT),
+        );
+        let with = |keysym, m: Modifiers| Keystroke::new(keysym, m).normalized();
+        assert_eq!(with(0x61, Modifiers::NUM_LOCK), a);
+        // Caps Lock turns the letter upper case without Shift being held
+        assert_eq!(with(0x41, Modifiers::LOCK), a);
+        assert_eq!(with(0x61, Modifiers::LOCK | Modifiers::SHIFT), shift_a);
+        assert_eq!(with(0x41, Modifiers::LOCKS | Modifiers::SHIFT), shift_a);
+        assert_eq!(
+            with(0xff51, Modifiers::LOCKS | Modifiers::CONTROL),
+            Keystroke::new(0xff51, Modifiers::CONTROL)
+        );
+    }
+
+    #[test]
+    fn parse_sequence_splits_on_commas() {
+        let ctrl_x = Keystroke::new(0x78, Modifiers::CONTROL);
+        assert_eq!(
+            parse_sequence("Ctrl x, n"),
+            Ok(vec![ctrl_x, Keystroke::from_raw(0x6e, 0)])
+        );
+        assert_eq!(
+            parse_sequence("Alt F4"),
+            Ok(vec![Keystroke::new(0xffc1, Modifiers::ALT)])
+        );
+        assert_eq!(
+            parse_sequence("Ctrl X"),
+            Ok(vec![Keystroke::new(
+                0x78,
+                Modifiers::CONTROL | Modifiers::SHIFT
+            )])
+        );
+        assert!(parse_sequence("Ctrl x,").is_err());
+        assert!(parse_sequence("Ctrl x, NoSuchKey").is_err());
+    }
+
+    #[test]
+    fn parse_errors_point_at_the_token() {
+        let err = |s: &str| {
+            let e = s.parse::<Keystroke>().unwrap_err();
+            (e.kind, e.token, e.offset)
+        };
+        assert_eq!(
+            err("Ctrl  PageUp"),
+            (ParseErrorKind::UnknownKey, "PageUp".to_owned(), 6)
+        );
+        assert_eq!(
+            err("Ctrl 0xzz"),
+            (ParseErrorKind::InvalidKeysym, "0xzz".to_owned(), 5)
+        );
+        assert_eq!(
+            err(" Ctrl Alt "),
+            (ParseErrorKind::MissingKey, "Ctrl Alt".to_owned(), 1)
+        );
+        assert_eq!(err(""), (ParseErrorKind::MissingKey, String::new(), 0));
+
+        let e = parse_sequence("Ctrl x, XF86Play").unwrap_err();
+        assert_eq!((e.token.as_str(), e.offset), ("XF86Play", 8));
+        assert_eq!(
+            parse_sequence("Ctrl x,").unwrap_err().to_string(),
+            "no key in \"\" at byte 7"
+        );
+    }
+
+    #[test]
+    fn parse_errors_suggest_key_names() {
+        let e = "Ctrl PageUp".parse::<Keystroke>().unwrap_err();
+        assert_eq!(e.suggestions.first(), Some(&"Page_Up"));
+        assert!(e
+            .to_string()
+            .starts_with("unknown key \"PageUp\" at byte 5, did you mean Page_Up"));
+
+        let e = ParseError {
+            suggestions: vec!["a", "b", "c"],
+            ..e
+        };
+        assert_eq!(
+            e.to_string(),
+            "unknown key \"PageUp\" at byte 5, did you mean a, b or c?"
+        );
+        let e = "0xzz".parse::<Keystroke>().unwrap_err();
+        assert_eq!(e.to_string(), "invalid keysym \"0xzz\" at byte 0");
+    }
+
+    #[test]
+    fn every_key_table_entry_round_trips() {
+        for &(name, keysym) in KEY_TABLE {
+            for bits in 0..64 {
+                let keystroke = Keystroke::new(keysym, modifiers(bits));
+                let text = keystroke.to_string();
+                assert_eq!(text.parse(), Ok(keystroke), "{name} as {text:?}");
+            }
+        }
+    }
+
+    proptest! {
+        #[test]
+        fn names_round_trip(i in 0..KEY_TABLE.len(), bits in 0u8..64) {
+            let (name, keysym) = KEY_TABLE[i];
+            let mods: Vec<&str> = MODIFIER_NAMES
+                .iter()
+                .enumerate()
+                .filter(|(i, _)| bits & (1 << i) != 0)
+                .map(|(_, (_, name))| *name)
+                .collect();
+            let text = format!("{} {name}", mods.join(" "));
+
+            let keystroke: Keystroke = text.parse().unwrap();
+            prop_assert_eq!(keystroke, Keystroke::new(keysym, modifiers(bits)));
+            prop_assert_eq!(keystroke.to_string().parse(), Ok(keystroke));
+        }
+
+        #[test]
+        fn any_keysym_round_trips(keysym in 1..0x2000_0000i32, bits in 0u8..64) {
+            let keystroke = Keystroke::new(keysym, modifiers(bits));
+            prop_assert_eq!(keystroke.to_string().parse(), Ok(keystroke));
+        }
+
+        #[test]
+        fn normalizing_is_idempotent(keysym in 1..0x2000_0000i32, bits in 0u8..64) {
+            let once = Keystroke::new(keysym, modifiers(bits)).normalized();
+            prop_assert_eq!(once.normalized(), once);
+        }
+    }
+}
@@ -1,7 +1,11 @@
+use lossycstring::LossyCString;
 use once_cell::sync::Lazy;
-use std::collections::HashMap;
+use std::{
+    collections::HashMap,
+    ffi::{CStr, CString},
+};
 
-static KEY_TABLE: &[(&str, i32)] = &[
+pub(crate) static KEY_TABLE: &[(&str, i32)] = &[
     ("VoidSymbol", 0xffffff),
     ("BackSpace", 0xff08),
     ("Tab", 0xff09),
@@ -2187,81 +2191,174 @@ static KEYMAP: Lazy<HashMap<&'static str, i32>> = Lazy::new(|| {
     m
 });
 
-fn keysym_from_str(name: &str) -> Option<i32> {
+// Reverse lookup. Several names may share a keysym (`Prior`/`Page_Up`), in
+// which case the first one listed in KEY_TABLE is the canonical name.
+static NAMEMAP: Lazy<HashMap<i32, CString>> = Lazy::new(|| {
+    let mut m = HashMap::with_capacity(KEY_TABLE.len());
+    for (name, code) in KEY_TABLE {
+        m.entry(*code).or_insert_with(|| LossyCString::new(name));
+    }
+    m
+});
+
+pub fn keysym_from_str(name: &str) -> Option<i32> {
     KEYMAP.get(name).copied()
 }
 
-pub const SHIFT_MASK:  i32 = 1 << 0;
-// pub const LOCK_MASK:   i32 = 1 << 1;
-pub const CONTROL_MASK:i32 = 1 << 2;
-pub const MOD1_MASK:   i32 = 1 << 3; // Alt
-// pub const MOD2_MASK:   i32 = 1 << 4;
-// pub const MOD3_MASK:   i32 = 1 << 5;
-pub const MOD4_MASK:   i32 = 1 << 6; // Super
-// pub const MOD5_MASK:   i32 = 1 << 7;
-
-pub fn parse_shortcut(s: &str) -> Option<(i32, i32)> {
-    let mut keycode: i32 = 0;
-    let mut modifiers: i32 = 0;
+/// Finds the spelling used in the keysym table for a name in any case,
+/// e.g. `backspace` gives `BackSpace`.
+pub fn keysym_name_ignore_case(name: &str) -> Option<&'static str> {
+    KEY_TABLE
+        .iter()
+        .find(|(n, _)| n.eq_ignore_ascii_case(name))
+        .map(|(n, _)| *n)
+}
 
-    // Split on whitespace like the C code does
-    for token in s.split_whitespace() {
-        let t = token.trim();
+/// Most suggestions [`similar_key_names`] gives.
+const MAX_SUGGESTIONS: usize = 3;
 
-        // Case-insensitive matching
-        let tl = t.to_ascii_lowercase();
+/// Key names close to an unknown one, best first: the same name but for case
+/// and underscores (`PageUp` for `Page_Up`), names containing all its words
+/// (`XF86Play` for `XF86AudioPlay`), then small typos.
+pub fn similar_key_names(name: &str) -> Vec<&'static str> {
+    let wanted = squash(name);
+    let wanted_words = words(name);
+    if wanted.is_empty() {
+        return Vec::new();
+    }
+    let max_typos = (wanted.len() / 3).max(1);
 
-        match tl.as_str() {
-            "ctrl" => {
-                modifiers |= CONTROL_MASK;
-                continue;
-            }
-            "alt" => {
-                modifiers |= MOD1_MASK;
-                continue;
+    let mut found: Vec<(usize, &'static str)> = KEY_TABLE
+        .iter()
+        .filter_map(|&(candidate, _)| {
+            let squashed = squash(candidate);
+            if squashed == wanted {
+                return Some((0, candidate));
             }
-            "shift" => {
-                modifiers |= SHIFT_MASK;
-                continue;
+            let candidate_words = words(candidate);
+            if wanted_words.len() > 1 && contains_in_order(&candidate_words, &wanted_words) {
+                return Some((candidate_words.len() - wanted_words.len(), candidate));
             }
-            "super" => {
-                modifiers |= MOD4_MASK;
-                continue;
-            }
-            _ => {} // fall through to key parsing
-        }
+            let typos = edit_distance(&squashed, &wanted);
+            (typos <= max_typos).then_some((typos + 1, candidate))
+        })
+        .collect();
+    found.sort_by_key(|&(score, _)| score);
+    found.truncate(MAX_SUGGESTIONS);
+    found.into_iter().map(|(_, name)| name).collect()
+}
 
-        // Try hex keycode: 0xFFAB style
-        if let Some(hex) = tl.strip_prefix("0x") {
-            match i32::from_str_radix(hex, 16) {
-                Ok(v) => {
-                    keycode = v;
-                }
-                Err(_) => {
-                    // identical to C: hex parse failure → keycode = 0
-                    keycode = 0;
-                }
-            }
-        } else {
-            // lookup name table
-            if let Some(mapped_keycode) = keysym_from_str(t) {
-                keycode = mapped_keycode
-            } else {
-                tracing::error!("got 0 from get_keycode while adding hotkey");
-                break;
-            }
+/// Lower case without underscores.
+fn squash(name: &str) -> String {
+    name.chars()
+        .filter(|&c| c != '_')
+        .map(|c| c.to_ascii_lowercase())
+        .collect()
+}
+
+/// Splits at underscores and where an upper case letter follows another
+/// character, `XF86AudioPlay` into `xf86`, `audio` and `play`.
+fn words(name: &str) -> Vec<String> {
+    let mut words: Vec<String> = Vec::new();
+    let mut prev: Option<char> = None;
+    for c in name.chars() {
+        if c == '_' {
+            prev = None;
+            continue;
+        }
+        let starts_word = match prev {
+            None => true,
+            Some(p) => c.is_ascii_uppercase() && !p.is_ascii_uppercase(),
+        };
+        if starts_word {
+            words.push(String::new());
+        }
+        if let Some(word) = words.last_mut() {
+            word.push(c.to_ascii_lowercase());
         }
+        prev = Some(c);
+    }
+    words
+}
 
-        if keycode == 0 {
-            // same behavior as the C version:
-            // stop parsing on first failure
-            return None;
+fn contains_in_order(haystack: &[String], needles: &[String]) -> bool {
+    let mut rest = haystack.iter();
+    needles.iter().all(|n| rest.any(|h| h == n))
+}
+
+/// Levenshtein distance.
+fn edit_distance(a: &str, b: &str) -> usize {
+    let b: Vec<char> = b.chars().collect();
+    let mut row: Vec<usize> = (0..=b.len()).collect();
+    for (i, ca) in a.chars().enumerate() {
+        let mut diagonal = row[0];
+        row[0] = i + 1;
+        for (j, &cb) in b.iter().enumerate() {
+            let substituted = diagonal + usize::from(ca != cb);
+            diagonal = row[j + 1];
+            row[j + 1] = substituted.min(row[j] + 1).min(diagonal + 1);
         }
     }
+    row[b.len()]
+}
+
+/// Returns the canonical name of a keysym.
+///
+/// The returned string lives as long as the plugin is loaded, so the pointer
+/// can be handed out to the C side.
+pub fn name_from_keysym(keysym: i32) -> Option<&'static CStr> {
+    NAMEMAP.get(&keysym).map(|name| name.as_c_str())
+}
+
+/// Whether a keysym is a modifier key on its own, like `Control_L`, which
+/// doesn't end a pending key sequence.
+pub fn is_modifier_keysym(keysym: i32) -> bool {
+    // Shift_L .. Hyper_R, ISO_Lock .. ISO_Level5_Lock, Mode_switch
+    matches!(keysym, 0xffe1..=0xffee | 0xfe01..=0xfe13 | 0xff7e)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn name_from_keysym_prefers_first_alias() {
+        assert_eq!(name_from_keysym(0xff55), Some(c"Prior"));
+        assert_eq!(name_from_keysym(0xff56), Some(c"Next"));
+    }
+
+    #[test]
+    fn name_from_keysym_round_trips_plain_keys() {
+        assert_eq!(name_from_keysym(0x0061), Some(c"a"));
+        assert_eq!(name_from_keysym(0xff51), Some(c"Left"));
+        assert_eq!(name_from_keysym(0x1008FF14), Some(c"XF86AudioPlay"));
+    }
+
+    #[test]
+    fn name_from_keysym_unknown() {
+        assert_eq!(name_from_keysym(0), None);
+        assert_eq!(name_from_keysym(0x7fff_fff0), None);
+    }
+
+    #[test]
+    fn suggests_similar_key_names() {
+        assert_eq!(similar_key_names("PageUp").first(), Some(&"Page_Up"));
+        assert_eq!(similar_key_names("pagedown").first(), Some(&"Page_Down"));
+        assert_eq!(
+            similar_key_names("XF86Play").first(),
+            Some(&"XF86AudioPlay")
+        );
+        assert_eq!(similar_key_names("Retrun").first(), Some(&"Return"));
+        assert!(similar_key_names("Escpe").contains(&"Escape"));
+        assert!(similar_key_names("NoSuchKeyAtAll").is_empty());
+        assert!(similar_key_names("").is_empty());
+    }
 
-    if keycode == 0 {
-        None
-    } else {
-        Some((keycode, modifiers))
+    #[test]
+    fn splits_key_names_into_words() {
+        assert_eq!(words("XF86AudioPlay"), ["xf86", "audio", "play"]);
+        assert_eq!(words("KP_Enter"), ["kp", "enter"]);
+        assert_eq!(words("a"), ["a"]);
+        assert_eq!(edit_distance("kitten", "sitting"), 3);
     }
 }
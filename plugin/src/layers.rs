@@ -0,0 +1,238 @@
+use std::time::{Duration, Instant};
+
+/// Config key for how long a layer stays active without being used, in ms,
+/// 0 for no timeout.
+pub const CONF_LAYER_TIMEOUT: &str = "hotkeys.layer_timeout";
+
+const STICKY_PREFIX: &str = "hotkeys_sticky_layer_";
+const ONE_SHOT_PREFIX: &str = "hotkeys_oneshot_layer_";
+const DEFAULT_LAYER_ACTION: &str = "hotkeys_default_layer";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Toggle {
+    /// Stays until switched off or timed out.
+    Sticky,
+    /// Only for the next hotkey.
+    OneShot,
+}
+
+/// Splits a `[layer]` prefix off a keystroke from the config,
+/// `"[playlist] Left"`. Bindings without one are in the default layer.
+pub fn split_layer(keystroke: &str) -> Result<(Option<&str>, &str), String> {
+    let keystroke = keystroke.trim();
+    let Some(rest) = keystroke.strip_prefix('[') else {
+        return Ok((None, keystroke));
+    };
+    let (name, keys) = rest
+        .split_once(']')
+        .ok_or_else(|| "missing closing bracket for layer".to_string())?;
+    let name = name.trim();
+    if name.is_empty()
+        || !name
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-')
+    {
+        return Err(format!("invalid layer name \"{name}\""));
+    }
+    Ok((Some(name), keys.trim()))
+}
+
+/// Names and titles of the actions switching to `layers`.
+pub fn layer_actions(layers: &[String]) -> Vec<(String, String)> {
+    let mut actions = vec![(
+        DEFAULT_LAYER_ACTION.to_owned(),
+        "Hotkeys/Default layer".to_owned(),
+    )];
+    for layer in layers {
+        actions.push((
+            format!("{STICKY_PREFIX}{layer}"),
+            format!("Hotkeys/Layer {layer}"),
+        ));
+        actions.push((
+            format!("{ONE_SHOT_PREFIX}{layer}"),
+            format!("Hotkeys/Layer {layer} for one hotkey"),
+        ));
+    }
+    actions
+}
+
+/// Which layer a layer action switches to, `None` for the default one.
+pub fn parse_layer_action(name: &str) -> Option<(Option<&str>, Toggle)> {
+    if name == DEFAULT_LAYER_ACTION {
+        return Some((None, Toggle::Sticky));
+    }
+    if let Some(layer) = name.strip_prefix(STICKY_PREFIX) {
+        return Some((Some(layer), Toggle::Sticky));
+    }
+    name.strip_prefix(ONE_SHOT_PREFIX)
+        .map(|layer| (Some(layer), Toggle::OneShot))
+}
+
+#[derive(Debug)]
+struct Active {
+    name: String,
+    toggle: Toggle,
+    timeout: Option<Duration>,
+    deadline: Option<Instant>,
+}
+
+/// The binding layer in use.
+#[derive(Debug, Default)]
+pub struct LayerState {
+    active: Option<Active>,
+}
+
+impl LayerState {
+    /// The active layer, `None` for the default one.
+    pub fn active(&mut self, now: Instant) -> Option<&str> {
+        let expired = self
+            .active
+            .as_ref()
+            .is_some_and(|a| a.deadline.is_some_and(|d| now >= d));
+        if expired {
+            self.active = None;
+        }
+        self.active.as_ref().map(|a| a.name.as_str())
+    }
+
+    /// Switches to a layer, `None` for the default one. Switching to the
+    /// sticky layer that is already active goes back to the default one.
+    pub fn switch(
+        &mut self,
+        name: Option<&str>,
+        toggle: Toggle,
+        timeout: Option<Duration>,
+        now: Instant,
+    ) {
+        let same = name.is_some() && self.active(now) == name;
+        let sticky = self
+            .active
+            .as_ref()
+            .is_some_and(|a| a.toggle == Toggle::Sticky);
+        self.active = match name {
+            Some(_) if same && sticky && toggle == Toggle::Sticky => None,
+            Some(name) => Some(Active {
+                name: name.to_owned(),
+                toggle,
+                timeout,
+                deadline: timeout.map(|t| now + t),
+            }),
+            None => None,
+        };
+    }
+
+    /// Called when a hotkey ran, ends a one-shot layer and restarts the
+    /// timeout of a sticky one.
+    pub fn used(&mut self, now: Instant) {
+        if self.active(now).is_none() {
+            return;
+        }
+        if let Some(a) = &mut self.active {
+            match a.toggle {
+                Toggle::OneShot => self.active = None,
+                Toggle::Sticky => a.deadline = a.timeout.map(|t| now + t),
+            }
+        }
+    }
+
+    /// Picks among bindings sharing a keystroke by their layers: the one in
+    /// the active layer, else the one in the default layer.
+    pub fn pick(&mut self, layers: &[Option<&str>], now: Instant) -> Option<usize> {
+        let active = self.active(now).map(str::to_owned);
+        let i = layers
+            .iter()
+            .position(|l| active.is_some() && *l == active.as_deref())
+            .or_else(|| layers.iter().position(Option::is_none))?;
+        self.used(now);
+        Some(i)
+    }
+
+    /// When the active layer times out, if it does.
+    pub fn deadline(&self) -> Option<Instant> {
+        self.active.as_ref().and_then(|a| a.deadline)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const TIMEOUT: Option<Duration> = Some(Duration::from_secs(5));
+
+    #[test]
+    fn splits_layers() {
+        assert_eq!(split_layer("Left"), Ok((None, "Left")));
+        assert_eq!(
+            split_layer("[playlist] Ctrl Left @repeat=100"),
+            Ok((Some("playlist"), "Ctrl Left @repeat=100"))
+        );
+        assert_eq!(split_layer(" [dj-2]Left"), Ok((Some("dj-2"), "Left")));
+        assert!(split_layer("[playlist Left").is_err());
+        assert!(split_layer("[] Left").is_err());
+        assert!(split_layer("[play list] Left").is_err());
+    }
+
+    #[test]
+    fn layer_action_names_round_trip() {
+        let layers = ["playlist".to_owned(), "oneshot_x".to_owned()];
+        let parsed: Vec<_> = layer_actions(&layers)
+            .iter()
+            .map(|(name, _)| parse_layer_action(name).map(|(l, t)| (l.map(str::to_owned), t)))
+            .collect();
+        assert_eq!(
+            parsed,
+            [
+                Some((None, Toggle::Sticky)),
+                Some((Some("playlist".to_owned()), Toggle::Sticky)),
+                Some((Some("playlist".to_owned()), Toggle::OneShot)),
+                Some((Some("oneshot_x".to_owned()), Toggle::Sticky)),
+                Some((Some("oneshot_x".to_owned()), Toggle::OneShot)),
+            ]
+        );
+        assert_eq!(parse_layer_action("seek_forward"), None);
+    }
+
+    #[test]
+    fn sticky_layer_toggles_and_times_out() {
+        let now = Instant::now();
+        let mut state = LayerState::default();
+
+        state.switch(Some("playlist"), Toggle::Sticky, None, now);
+        assert_eq!(state.active(now), Some("playlist"));
+        state.used(now);
+        assert_eq!(state.active(now), Some("playlist"));
+        state.switch(Some("playlist"), Toggle::Sticky, None, now);
+        assert_eq!(state.active(now), None);
+
+        state.switch(Some("playlist"), Toggle::Sticky, TIMEOUT, now);
+        let later = now + Duration::from_secs(4);
+        state.used(later);
+        assert_eq!(state.active(now + Duration::from_secs(8)), Some("playlist"));
+        assert_eq!(state.active(now + Duration::from_secs(9)), None);
+    }
+
+    #[test]
+    fn one_shot_layer_lasts_one_hotkey() {
+        let now = Instant::now();
+        let mut state = LayerState::default();
+
+        state.switch(Some("playlist"), Toggle::OneShot, None, now);
+        assert_eq!(state.pick(&[None, Some("playlist")], now), Some(1));
+        assert_eq!(state.active(now), None);
+        assert_eq!(state.pick(&[None, Some("playlist")], now), Some(0));
+        assert_eq!(state.pick(&[Some("playlist")], now), None);
+    }
+
+    #[test]
+    fn default_layer_switch() {
+        let now = Instant::now();
+        let mut state = LayerState::default();
+
+        state.switch(Some("playlist"), Toggle::Sticky, None, now);
+        state.switch(None, Toggle::Sticky, None, now);
+        assert_eq!(state.active(now), None);
+        // Keys not in the active layer fall back to the default one
+        state.switch(Some("playlist"), Toggle::Sticky, None, now);
+        assert_eq!(state.pick(&[Some("other"), None], now), Some(1));
+    }
+}
@@ -1,16 +1,41 @@
 use deadbeef_sys::*;
 use once_cell::sync::Lazy;
 use std::{
-    ffi::{c_char, c_int},
-    sync::Mutex,
+    ffi::{c_char, c_int, CString},
+    sync::{Arc, Mutex},
+    time::Instant,
 };
 
-mod utils;
+mod actions;
+mod backend;
+mod builtin;
+mod clock;
+mod condition;
+mod conflicts;
+#[cfg(feature = "evdev")]
+mod evdev;
+mod exec;
+mod keystroke;
+mod keysyms;
+mod layers;
+mod macros;
+mod modes;
 mod plugin;
+mod profiles;
+mod reserved;
+mod sequence;
 mod shortcuthandler;
+mod trigger;
+mod utils;
+mod x11;
+
+use backend::KeyEvent;
+use keystroke::Keystroke;
 use plugin::*;
 
-mod keysyms;
+/// The plugin's own actions. Kept apart from [`PLUGIN`] since DeadBeef asks
+/// for them while the plugin looks up actions.
+static ACTIONS: Lazy<Mutex<actions::ActionList>> = Lazy::new(Default::default);
 
 static PLUGIN: Lazy<Mutex<MiscPlugin>> = Lazy::new(|| {
     let x = DB_hotkeys_plugin_t {
@@ -32,9 +57,9 @@ static PLUGIN: Lazy<Mutex<MiscPlugin>> = Lazy::new(|| {
                 website: c"https://saivert.com".as_ptr(),
                 start: Some(plugin_start),
                 stop: Some(plugin_stop),
-                message: None,
+                message: Some(message),
                 connect: None,
-                get_actions: None,
+                get_actions: Some(get_actions),
                 exec_cmdline: None,
                 disconnect: None,
                 command: None,
@@ -55,44 +80,216 @@ extern "C" fn get_action_for_keycombo(
     isglobal: i32,
     ctx: *mut ddb_action_context_t,
 ) -> *mut DB_plugin_action_t {
+    let keystroke = Keystroke::from_raw(key, mods);
     if let Ok(p) = &mut PLUGIN.lock() {
-        if let Some((context, action_ptr)) = p.get_action_for_keycombo(key, mods, isglobal) {
+        if let Some((context, action_ptr)) = p.get_action_for_keycombo(keystroke, isglobal) {
             unsafe { *ctx = context }
             return action_ptr;
         }
     }
-    return std::ptr::null_mut();
+    std::ptr::null_mut()
+}
+
+extern "C" fn get_name_for_keycode(keycode: i32) -> *const c_char {
+    match keysyms::name_from_keysym(keycode) {
+        Some(name) => name.as_ptr(),
+        None => {
+            tracing::debug!("get_name_for_keycode: no name for {keycode:#x}");
+            std::ptr::null()
+        }
+    }
+}
+
+extern "C" fn get_actions(_it: *mut DB_playItem_t) -> *mut DB_plugin_action_t {
+    ACTIONS
+        .lock()
+        .map_or(std::ptr::null_mut(), |mut a| a.head())
+}
+
+/// Callback of the plugin's own actions, which tell each other apart by name.
+extern "C" fn run_action(action: *mut DB_plugin_action_t, ctx: ddb_action_context_t) -> c_int {
+    let Some(name) = deadbeef_sys::plugin::Action::from_raw(action)
+        .ok()
+        .and_then(|a| a.name().map(str::to_owned))
+    else {
+        return -1;
+    };
+
+    if let Some((layer, toggle)) = layers::parse_layer_action(&name) {
+        if let Ok(mut p) = PLUGIN.lock() {
+            p.switch_layer(layer, toggle);
+        }
+        return 0;
+    }
+    if let Some(action) = profiles::parse_profile_action(&name) {
+        profiles::run_profile_action(action);
+        return 0;
+    }
+    if let Some(Ok(steps)) = macros::parse_macro(&name) {
+        if let Ok(mut p) = PLUGIN.lock() {
+            p.toggle_macro(&name, steps, ctx);
+        }
+        return 0;
+    }
+    if let Some(Ok(args)) = exec::parse_command(&name) {
+        exec::run_command(&args);
+        return 0;
+    }
+    if let Some(Ok(builtin)) = builtin::Builtin::parse(&name) {
+        builtin.run();
+        return 0;
+    }
+    -1
+}
+
+/// Called when the active layer may have timed out.
+fn layer_timeout() {
+    if let Ok(mut p) = PLUGIN.lock() {
+        p.sync_layer();
+        p.schedule_layer_timeout();
+    }
+}
+
+/// Picks among global bindings sharing a keystroke by their layers.
+fn pick_layer(layers: &[Option<&str>]) -> Option<usize> {
+    PLUGIN.lock().ok()?.pick_layer(layers)
+}
+
+/// Runs the global hotkey for a keystroke reported by a backend.
+///
+/// Returns when the key sequence the keystroke is part of times out, if
+/// there is one in progress.
+fn dispatch_global(keystroke: Keystroke, event: KeyEvent) -> Option<Instant> {
+    // Don't hold the lock while the action runs, it may call back into us.
+    let (fire, deadline) = match PLUGIN.lock() {
+        Ok(mut p) => (
+            p.global_key_event(keystroke, event),
+            p.global_sequence_deadline(),
+        ),
+        Err(_) => (None, None),
+    };
+
+    if let Some(fire) = fire {
+        fire();
+    }
+    deadline
+}
+
+/// Stops the running backend.
+///
+/// This joins the backend's worker thread, which may be stuck waiting on the
+/// portal for a while or be dispatching through the plugin, so the plugin lock
+/// is not held meanwhile.
+fn stop_backend() {
+    let backend = PLUGIN.lock().ok().and_then(|mut p| p.take_backend());
+    if let Some(b) = backend {
+        b.stop();
+    }
 }
 
-extern "C" fn get_name_for_keycode(_keycode: i32) -> *const c_char {
-    tracing::debug!("get_name_for_keycode {_keycode}");
-    return std::ptr::null_mut();
+/// Starts a backend with the global keys of the current config. Like
+/// [`stop_backend`], this does not hold the plugin lock while the backend
+/// starts up.
+fn start_backend() {
+    let Some(keys) = PLUGIN.lock().ok().map(|mut p| p.backend_keys()) else {
+        return;
+    };
+    let backend = backend::start(&keys, Arc::new(dispatch_global));
+
+    let old = match PLUGIN.lock() {
+        Ok(mut p) => p.set_backend(backend, &keys),
+        Err(_) => backend,
+    };
+    if let Some(b) = old {
+        b.stop();
+    }
 }
 
 extern "C" fn reset() {
-    tracing::debug!("reset");
+    stop_backend();
+
+    if let Ok(p) = &mut PLUGIN.lock() {
+        p.reset();
+    }
+    start_backend();
 }
 
 extern "C" fn plugin_start() -> c_int {
     if let Ok(p) = &mut PLUGIN.lock() {
         p.plugin_start();
     }
+    start_backend();
     0
 }
 
 extern "C" fn plugin_stop() -> c_int {
-    if let Ok(p) = &mut PLUGIN.lock() {
-        p.plugin_stop();
+    stop_backend();
+    if let Ok(mut p) = PLUGIN.lock() {
+        p.cancel_macros();
     }
     0
 }
 
-// extern "C" fn message(msgid: u32, ctx: usize, p1: u32, p2: u32) -> c_int {
-//     if let Ok(p) = PLUGIN.lock() {
-//         p.message(msgid, ctx, p1, p2);
-//     }
-//     0
-// }
+extern "C" fn message(msgid: u32, _ctx: usize, _p1: u32, _p2: u32) -> c_int {
+    match msgid {
+        DB_EV_CONFIGCHANGED => {
+            let changed = PLUGIN.lock().is_ok_and(|p| p.hotkeys_changed());
+            if changed {
+                tracing::debug!("hotkey configuration changed");
+                reset();
+            }
+        }
+        DB_EV_ACTIONSCHANGED | DB_EV_PLUGINSLOADED => {
+            if let Ok(p) = &mut PLUGIN.lock() {
+                p.invalidate_actions();
+                if msgid == DB_EV_PLUGINSLOADED {
+                    p.plugins_loaded();
+                }
+            }
+            if msgid == DB_EV_ACTIONSCHANGED {
+                if let Ok(mut a) = ACTIONS.lock() {
+                    a.release_retired();
+                }
+            }
+        }
+        _ => {}
+    }
+    0
+}
+
+/// Name of the backend capturing global hotkeys, `portal`, `x11` or `evdev`,
+/// or null when there is none.
+#[no_mangle]
+pub extern "C" fn deadbeef_hotkeys_rust_active_backend() -> *const c_char {
+    PLUGIN
+        .lock()
+        .ok()
+        .and_then(|p| p.backend_kind())
+        .map_or(std::ptr::null(), |k| k.as_cstr().as_ptr())
+}
+
+/// Conflicts between the hotkey bindings, a `kind\tfirst\tsecond` line for
+/// each with the config keys of the bindings and `kind` one of `duplicate`,
+/// `prefix` or `local-global`. The caller owns the copy and releases it with
+/// [`deadbeef_hotkeys_rust_free_conflicts`].
+#[no_mangle]
+pub extern "C" fn deadbeef_hotkeys_rust_conflicts() -> *mut c_char {
+    PLUGIN.lock().map_or(std::ptr::null_mut(), |p| {
+        p.conflict_report().to_owned().into_raw()
+    })
+}
+
+/// Releases a report returned by [`deadbeef_hotkeys_rust_conflicts`].
+///
+/// # Safety
+/// `report` must come from [`deadbeef_hotkeys_rust_conflicts`] and not have
+/// been released yet, or be null.
+#[no_mangle]
+pub unsafe extern "C" fn deadbeef_hotkeys_rust_free_conflicts(report: *mut c_char) {
+    if !report.is_null() {
+        drop(CString::from_raw(report));
+    }
+}
 
 #[no_mangle]
 ///
@@ -0,0 +1,230 @@
+use crate::{
+    actions::bound_actions,
+    clock::{Clock, SmolClock},
+    utils::escape_title,
+};
+use smol::Task;
+use std::{collections::HashMap, time::Duration};
+
+const NAME: &str = "hotkeys_macro";
+
+/// One step of a macro binding.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Step {
+    /// An action name, with the parameter for built-in ones.
+    Action(String),
+    Wait(Duration),
+}
+
+impl Step {
+    pub fn action(&self) -> Option<&str> {
+        match self {
+            Step::Action(name) => Some(name),
+            Step::Wait(_) => None,
+        }
+    }
+}
+
+/// Parses the action of a binding line as a macro, its steps after a colon
+/// separated by `;` like
+/// `hotkeys_macro:stop;hotkeys_playlist:Radio;wait:500;hotkeys_play_item:1`.
+/// Steps are action names, which are single tokens with their parameters
+/// escaped, so the macro stays a single token in the config line too.
+///
+/// Returns `None` for other actions.
+pub fn parse_macro(action: &str) -> Option<Result<Vec<Step>, String>> {
+    let rest = action.trim().strip_prefix(NAME)?;
+    let steps = match rest.strip_prefix(':') {
+        Some(steps) => steps,
+        None if rest.is_empty() => return Some(Err(format!("{NAME}: missing steps"))),
+        None => return None,
+    };
+    if steps.contains(char::is_whitespace) {
+        return Some(Err(format!(
+            "{action}: steps are separated by ; without spaces"
+        )));
+    }
+    let steps = steps
+        .split(';')
+        .map(parse_step)
+        .collect::<Result<Vec<_>, _>>()
+        .and_then(|steps| {
+            if steps.iter().any(|s| s.action().is_some()) {
+                Ok(steps)
+            } else {
+                Err(format!("macro \"{action}\" runs no action"))
+            }
+        });
+    Some(steps)
+}
+
+fn parse_step(step: &str) -> Result<Step, String> {
+    if step.is_empty() {
+        return Err("empty macro step".to_string());
+    }
+    match step.strip_prefix("wait:") {
+        Some(ms) => ms
+            .parse::<u64>()
+            .map(|ms| Step::Wait(Duration::from_millis(ms)))
+            .map_err(|_| format!("invalid time \"{ms}\" to wait")),
+        None => Ok(Step::Action(step.to_owned())),
+    }
+}
+
+/// The macro actions to offer, see [`bound_actions`].
+pub fn macro_actions<'a>(bound: impl IntoIterator<Item = &'a str>) -> Vec<(String, String)> {
+    bound_actions(bound, |name| {
+        let steps: Vec<_> = parse_macro(name)?
+            .ok()?
+            .iter()
+            .map(|step| match step {
+                Step::Action(name) => escape_title(name),
+                Step::Wait(d) => format!("wait {} ms", d.as_millis()),
+            })
+            .collect();
+        Some(format!("Hotkeys/Macro: {}", steps.join(", ")))
+    })
+}
+
+/// Running macros by their action name. They run on smol's global executor
+/// unless given another [`Clock`], and are cancelled by dropping their task.
+#[derive(Default)]
+pub struct MacroRunner<C = SmolClock> {
+    clock: C,
+    running: HashMap<String, Task<()>>,
+}
+
+impl<C: Clock> MacroRunner<C> {
+    #[cfg(test)]
+    pub fn with_clock(clock: C) -> Self {
+        Self {
+            clock,
+            running: HashMap::new(),
+        }
+    }
+
+    /// Starts a macro, running each action with `run`, or cancels it when it
+    /// is still running from the last time.
+    pub fn toggle<F>(&mut self, name: &str, steps: Vec<Step>, run: F)
+    where
+        F: Fn(&str) + Send + 'static,
+    {
+        self.running.retain(|_, task| !task.is_finished());
+        if self.running.remove(name).is_some() {
+            tracing::debug!("Cancelled macro \"{name}\"");
+            return;
+        }
+
+        let clock = self.clock.clone();
+        let task = self.clock.spawn(async move {
+            for step in steps {
+                match step {
+                    Step::Action(action) => run(&action),
+                    Step::Wait(d) => clock.sleep(d).await,
+                }
+            }
+        });
+        self.running.insert(name.to_owned(), task);
+    }
+
+    /// Cancels all running macros.
+    pub fn clear(&mut self) {
+        self.running.clear();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::clock::ManualClock;
+    use std::sync::{Arc, Mutex};
+
+    fn ms(ms: u64) -> Duration {
+        Duration::from_millis(ms)
+    }
+
+    #[test]
+    fn parses_macros() {
+        assert_eq!(parse_macro("stop"), None);
+        assert_eq!(
+            parse_macro("hotkeys_macro:stop;hotkeys_playlist:Radio;wait:500;hotkeys_play_item:1"),
+            Some(Ok(vec![
+                Step::Action("stop".to_owned()),
+                Step::Action("hotkeys_playlist:Radio".to_owned()),
+                Step::Wait(ms(500)),
+                Step::Action("hotkeys_play_item:1".to_owned()),
+            ]))
+        );
+        // Escaped parameters of the steps stay as they are
+        assert_eq!(
+            parse_macro("hotkeys_macro:hotkeys_exec:sh,-c,mpc%20pause%3B%20mpc%20next;wait:10"),
+            Some(Ok(vec![
+                Step::Action("hotkeys_exec:sh,-c,mpc%20pause%3B%20mpc%20next".to_owned()),
+                Step::Wait(ms(10)),
+            ]))
+        );
+        assert_eq!(
+            parse_macro("hotkeys_macro:stop"),
+            Some(Ok(vec![Step::Action("stop".to_owned())]))
+        );
+        for action in [
+            "hotkeys_macro",
+            "hotkeys_macro:",
+            "hotkeys_macro:stop;",
+            "hotkeys_macro:stop;wait:",
+            "hotkeys_macro:stop;wait:soon",
+            "hotkeys_macro:wait:10;wait:20",
+            // Spaces would be squeezed when the line is read
+            "hotkeys_macro:stop; play",
+        ] {
+            assert!(matches!(parse_macro(action), Some(Err(_))), "{action:?}");
+        }
+        assert_eq!(parse_macro("hotkeys_macros:stop"), None);
+    }
+
+    #[test]
+    fn titles_macros() {
+        assert_eq!(
+            macro_actions([
+                "stop",
+                "hotkeys_macro:stop;wait:100;hotkeys_playlist:A/B",
+                "hotkeys_macro:stop;play"
+            ]),
+            [
+                (
+                    "hotkeys_macro:stop;wait:100;hotkeys_playlist:A/B".to_owned(),
+                    "Hotkeys/Macro: stop, wait 100 ms, hotkeys_playlist:A\\/B".to_owned()
+                ),
+                (
+                    "hotkeys_macro:stop;play".to_owned(),
+                    "Hotkeys/Macro: stop, play".to_owned()
+                ),
+            ]
+        );
+    }
+
+    #[test]
+    fn runs_and_cancels_macros() {
+        let ran = Arc::new(Mutex::new(Vec::new()));
+        let run = {
+            let ran = ran.clone();
+            move |action: &str| ran.lock().unwrap().push(action.to_owned())
+        };
+        let steps = parse_macro("hotkeys_macro:a;wait:50;b").unwrap().unwrap();
+        let clock = ManualClock::default();
+        let mut runner = MacroRunner::with_clock(clock.clone());
+
+        runner.toggle("m", steps.clone(), run.clone());
+        clock.advance(ms(49));
+        assert_eq!(*ran.lock().unwrap(), ["a"]);
+        clock.advance(ms(1));
+        assert_eq!(*ran.lock().unwrap(), ["a", "b"]);
+
+        // Pressed again while waiting
+        runner.toggle("m", steps.clone(), run.clone());
+        clock.advance(ms(20));
+        runner.toggle("m", steps, run);
+        clock.advance(ms(100));
+        assert_eq!(*ran.lock().unwrap(), ["a", "b", "a"]);
+    }
+}
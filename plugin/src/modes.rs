@@ -0,0 +1,313 @@
+use crate::clock::{Clock, SmolClock};
+use smol::Task;
+use std::{
+    collections::HashMap,
+    fmt,
+    hash::Hash,
+    sync::Arc,
+    time::{Duration, Instant},
+};
+
+const DEFAULT_LONG_PRESS: u64 = 500;
+const DEFAULT_DOUBLE_TAP: u64 = 300;
+const DEFAULT_REPEAT: u64 = 100;
+
+/// Runs a binding's action.
+pub type Fire = Arc<dyn Fn() + Send + Sync>;
+
+/// When a binding runs its action, written after the keystroke in the config
+/// like `"Ctrl Right @repeat=150"`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Mode {
+    #[default]
+    Press,
+    Release,
+    /// Once the key is held this long.
+    LongPress(Duration),
+    /// On the second press within this time.
+    DoubleTap(Duration),
+    /// On press, then at this interval while held.
+    Repeat(Duration),
+}
+
+impl Mode {
+    /// Splits the mode off a keystroke from the config.
+    pub fn split(keystroke: &str) -> Result<(&str, Mode), String> {
+        let Some((keys, mode)) = keystroke.rsplit_once('@') else {
+            return Ok((keystroke.trim(), Mode::Press));
+        };
+
+        let (name, ms) = match mode.trim().split_once('=') {
+            Some((name, ms)) => {
+                let ms = ms
+                    .trim()
+                    .parse::<u64>()
+                    .ok()
+                    .filter(|&ms| ms > 0)
+                    .ok_or_else(|| format!("invalid time \"{ms}\" in trigger mode"))?;
+                (name.trim(), Some(Duration::from_millis(ms)))
+            }
+            None => (mode.trim(), None),
+        };
+        let or = |default| ms.unwrap_or(Duration::from_millis(default));
+
+        let mode = match name {
+            "press" => Mode::Press,
+            "release" => Mode::Release,
+            "long-press" => Mode::LongPress(or(DEFAULT_LONG_PRESS)),
+            "double-tap" => Mode::DoubleTap(or(DEFAULT_DOUBLE_TAP)),
+            "repeat" => Mode::Repeat(or(DEFAULT_REPEAT)),
+            _ => return Err(format!("unknown trigger mode \"{name}\"")),
+        };
+        Ok((keys.trim(), mode))
+    }
+
+    /// Inverse of [`Self::split`].
+    pub fn join(self, keys: &str) -> String {
+        match self {
+            Mode::Press => keys.to_owned(),
+            mode => format!("{keys} @{mode}"),
+        }
+    }
+}
+
+impl fmt::Display for Mode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Mode::Press => write!(f, "press"),
+            Mode::Release => write!(f, "release"),
+            Mode::LongPress(d) => write!(f, "long-press={}", d.as_millis()),
+            Mode::DoubleTap(d) => write!(f, "double-tap={}", d.as_millis()),
+            Mode::Repeat(d) => write!(f, "repeat={}", d.as_millis()),
+        }
+    }
+}
+
+struct Held {
+    mode: Mode,
+    fire: Fire,
+    /// Long-press or repeat timer, cancelled on drop.
+    _timer: Option<Task<()>>,
+}
+
+/// Applies trigger modes to presses and releases of bindings identified by `K`.
+///
+/// Actions due right away are handed back so the caller can run them outside
+/// its locks, the timed ones run from the [`Clock`], smol's executor by default.
+pub struct ModeRunner<K, C = SmolClock> {
+    clock: C,
+    held: HashMap<K, Held>,
+    /// When each double-tap binding was last tapped.
+    taps: HashMap<K, Instant>,
+}
+
+impl<K> Default for ModeRunner<K> {
+    fn default() -> Self {
+        Self {
+            clock: SmolClock,
+            held: HashMap::new(),
+            taps: HashMap::new(),
+        }
+    }
+}
+
+impl<K: Hash + Eq + Clone, C: Clock> ModeRunner<K, C> {
+    #[cfg(test)]
+    pub fn with_clock(clock: C) -> Self {
+        Self {
+            clock,
+            held: HashMap::new(),
+            taps: HashMap::new(),
+        }
+    }
+
+    /// Handles a binding being pressed, returning its action if it is due now.
+    pub fn press(&mut self, key: K, mode: Mode, fire: Fire, now: Instant) -> Option<Fire> {
+        // Key repeat of a binding that is already held
+        if self.held.contains_key(&key) {
+            return None;
+        }
+
+        let (run, timer) = match mode {
+            Mode::Press => (true, None),
+            Mode::Release => (false, None),
+            Mode::LongPress(after) => {
+                let (fire, clock) = (fire.clone(), self.clock.clone());
+                let timer = self.clock.spawn(async move {
+                    clock.sleep(after).await;
+                    fire();
+                });
+                (false, Some(timer))
+            }
+            Mode::DoubleTap(within) => {
+                let second = self
+                    .taps
+                    .remove(&key)
+                    .is_some_and(|last| now.duration_since(last) <= within);
+                if !second {
+                    self.taps.insert(key.clone(), now);
+                }
+                (second, None)
+            }
+            Mode::Repeat(interval) => {
+                let (fire, clock) = (fire.clone(), self.clock.clone());
+                let timer = self.clock.spawn(async move {
+                    loop {
+                        clock.sleep(interval).await;
+                        fire();
+                    }
+                });
+                (true, Some(timer))
+            }
+        };
+
+        let now = run.then(|| fire.clone());
+        self.held.insert(
+            key,
+            Held {
+                mode,
+                fire,
+                _timer: timer,
+            },
+        );
+        now
+    }
+
+    /// Handles a binding being released, returning its action if it is due now.
+    pub fn release(&mut self, key: &K) -> Option<Fire> {
+        let held = self.held.remove(key)?;
+        (held.mode == Mode::Release).then_some(held.fire)
+    }
+
+    /// Forgets everything held, stopping the timers.
+    pub fn clear(&mut self) {
+        self.held.clear();
+        self.taps.clear();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::clock::ManualClock;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    fn counter() -> (Arc<AtomicUsize>, Fire) {
+        let count = Arc::new(AtomicUsize::new(0));
+        let c = count.clone();
+        (
+            count,
+            Arc::new(move || {
+                c.fetch_add(1, Ordering::SeqCst);
+            }),
+        )
+    }
+
+    fn ms(ms: u64) -> Duration {
+        Duration::from_millis(ms)
+    }
+
+    #[test]
+    fn splits_modes() {
+        let table = [
+            ("Ctrl Right", "Ctrl Right", Mode::Press),
+            ("Ctrl Right @press", "Ctrl Right", Mode::Press),
+            ("Escape @release", "Escape", Mode::Release),
+            ("p @long-press", "p", Mode::LongPress(ms(500))),
+            ("p@long-press=800", "p", Mode::LongPress(ms(800))),
+            ("p @double-tap = 250", "p", Mode::DoubleTap(ms(250))),
+            ("Ctrl x, n @repeat=150", "Ctrl x, n", Mode::Repeat(ms(150))),
+        ];
+        for (keystroke, keys, mode) in table {
+            assert_eq!(Mode::split(keystroke), Ok((keys, mode)), "{keystroke:?}");
+        }
+
+        for keystroke in ["p @hold", "p @repeat=0", "p @repeat=fast", "p @"] {
+            assert!(Mode::split(keystroke).is_err(), "{keystroke:?}");
+        }
+    }
+
+    #[test]
+    fn join_round_trips() {
+        for keystroke in ["Ctrl Right", "Escape @release", "p @long-press=800"] {
+            let (keys, mode) = Mode::split(keystroke).unwrap();
+            assert_eq!(mode.join(keys), keystroke);
+        }
+    }
+
+    #[test]
+    fn press_and_release() {
+        let (_, fire) = counter();
+        let now = Instant::now();
+        let mut runner = ModeRunner::default();
+
+        assert!(runner.press(1, Mode::Press, fire.clone(), now).is_some());
+        // Held down, the key repeats
+        assert!(runner.press(1, Mode::Press, fire.clone(), now).is_none());
+        assert!(runner.release(&1).is_none());
+
+        assert!(runner.press(2, Mode::Release, fire.clone(), now).is_none());
+        assert!(runner.release(&2).is_some());
+        assert!(runner.release(&2).is_none());
+    }
+
+    #[test]
+    fn double_tap() {
+        let (_, fire) = counter();
+        let now = Instant::now();
+        let mode = Mode::DoubleTap(ms(300));
+        let mut runner = ModeRunner::default();
+
+        assert!(runner.press(1, mode, fire.clone(), now).is_none());
+        runner.release(&1);
+        assert!(runner.press(1, mode, fire.clone(), now + ms(200)).is_some());
+        runner.release(&1);
+        // A third tap starts over
+        assert!(runner.press(1, mode, fire.clone(), now + ms(300)).is_none());
+        runner.release(&1);
+        assert!(runner.press(1, mode, fire.clone(), now + ms(700)).is_none());
+    }
+
+    #[test]
+    fn long_press_fires_once_held_long_enough() {
+        let (count, fire) = counter();
+        let clock = ManualClock::default();
+        let mut runner = ModeRunner::with_clock(clock.clone());
+
+        assert!(runner
+            .press(1, Mode::LongPress(ms(50)), fire.clone(), Instant::now())
+            .is_none());
+        clock.advance(ms(49));
+        runner.release(&1);
+        clock.advance(ms(100));
+        assert_eq!(count.load(Ordering::SeqCst), 0);
+
+        runner.press(1, Mode::LongPress(ms(50)), fire, Instant::now());
+        clock.advance(ms(49));
+        assert_eq!(count.load(Ordering::SeqCst), 0);
+        clock.advance(ms(1));
+        assert_eq!(count.load(Ordering::SeqCst), 1);
+        clock.advance(ms(100));
+        assert_eq!(count.load(Ordering::SeqCst), 1);
+        runner.release(&1);
+    }
+
+    #[test]
+    fn repeat_fires_while_held() {
+        let (count, fire) = counter();
+        let clock = ManualClock::default();
+        let mut runner = ModeRunner::with_clock(clock.clone());
+
+        assert!(runner
+            .press(1, Mode::Repeat(ms(20)), fire, Instant::now())
+            .is_some());
+        clock.advance(ms(19));
+        assert_eq!(count.load(Ordering::SeqCst), 0);
+        clock.advance(ms(81));
+        assert_eq!(count.load(Ordering::SeqCst), 5);
+
+        runner.release(&1);
+        clock.advance(ms(100));
+        assert_eq!(count.load(Ordering::SeqCst), 5);
+    }
+}
@@ -1,43 +1,87 @@
-use crate::{keysyms::parse_shortcut, shortcuthandler::ShortcutHandler, utils::parse_line, *};
-use async_lock::Mutex;
-use futures_util::future::AbortHandle;
-use std::{sync::Arc, thread};
+use crate::{
+    backend::{self, Backend, KeyEvent},
+    builtin::{builtin_actions, Builtin},
+    condition::{split_condition, Condition},
+    conflicts::{self, find_conflicts},
+    exec::{self, command_actions, parse_command, CONF_COMMANDS_ENABLED},
+    keystroke::{parse_sequence, Keystroke},
+    layers::{self, split_layer, LayerState, Toggle, CONF_LAYER_TIMEOUT},
+    macros::{self, macro_actions, parse_macro, MacroRunner},
+    modes::{Fire, Mode, ModeRunner},
+    profiles::{self, hotkey_items, profile_actions},
+    reserved::{find_reserved, Desktop},
+    sequence::{SequenceState, Step, CONF_SEQUENCE_TIMEOUT, DEFAULT_SEQUENCE_TIMEOUT},
+    utils::{log_to_deadbeef, parse_line},
+    *,
+};
+use std::{
+    ffi::{CStr, CString},
+    ptr::NonNull,
+    sync::Arc,
+    time::{Duration, Instant},
+};
 
 pub struct MiscPlugin {
     plugin: DB_hotkeys_plugin_t,
-    thread: Option<PluginThread>,
-    shortcut_handler: Arc<Mutex<ShortcutHandler>>,
-    abort_handle: Arc<Mutex<Option<AbortHandle>>>,
+    backend: Option<Box<dyn Backend>>,
     commands: Vec<Command>,
+    /// `hotkey.*` key/value pairs the commands were built from.
+    config: Vec<(String, String)>,
+    /// Value of [`backend::CONF_BACKEND`] the backend was started with.
+    backend_config: String,
+    /// Key sequences in progress, typed in DeadBeef's window and globally.
+    local_sequence: SequenceState,
+    global_sequence: SequenceState,
+    /// Global bindings held down, by the keystroke that completed them.
+    held: ModeRunner<Keystroke>,
+    layers: LayerState,
+    macros: MacroRunner,
+    /// Conflicts between the bindings, see [`conflicts::report`].
+    conflict_report: CString,
+    /// Layer the backend's grabbed keys were last set for.
+    grabbed_layer: Option<String>,
+    /// Whether every plugin had the chance to register its actions.
+    plugins_loaded: bool,
 }
 
-#[derive(Debug, Clone, Copy)]
+#[derive(Debug, Clone)]
 pub struct Command {
-    keycode: i32,
-    modifier: i32,
+    /// The config key, `hotkey.key07`.
+    id: String,
+    /// Keystrokes to type in turn, usually just one.
+    sequence: Vec<Keystroke>,
+    mode: Mode,
+    /// `None` for the default layer.
+    layer: Option<String>,
+    /// Only applies while this holds.
+    condition: Option<Condition>,
     ctx: ddb_action_context_t,
     isglobal: i32,
-    action: *mut DB_plugin_action_t,
+    action_name: String,
+    /// Cached result of looking up `action_name`, cleared whenever the
+    /// plugins' action lists change.
+    action: Option<NonNull<DB_plugin_action_t>>,
+    /// Whether `action_name` was reported as unknown already.
+    warned: bool,
 }
 
-unsafe impl Send for MiscPlugin {}
-
-struct PluginThread {
-    handle: thread::JoinHandle<()>,
-}
-
-impl PluginThread {
-    pub fn new(plugin: Arc<Mutex<ShortcutHandler>>) -> Self {
-        Self {
-            handle: thread::spawn(move || thread_main(plugin)),
+impl Command {
+    fn action(&mut self) -> Option<NonNull<DB_plugin_action_t>> {
+        if self.action.is_none() {
+            self.action = DeadBeef::find_action_by_name(&self.action_name)
+                .and_then(|x| NonNull::new(x.as_ptr()));
+            if self.action.is_none() && !self.warned {
+                let msg = format!("{}: unknown action \"{}\"", self.id, self.action_name);
+                log_to_deadbeef(DDB_LOG_LAYER_DEFAULT, &msg);
+                self.warned = true;
+            }
         }
-    }
-
-    pub fn join(self) -> thread::Result<()> {
-        self.handle.join()
+        self.action
     }
 }
 
+unsafe impl Send for MiscPlugin {}
+
 impl DBPlugin for MiscPlugin {
     fn get_plugin_ptr(&self) -> *const DB_plugin_t {
         &self.plugin as *const DB_hotkeys_plugin_t as *const DB_plugin_t
@@ -46,14 +90,20 @@ impl DBPlugin for MiscPlugin {
 
 impl MiscPlugin {
     pub fn new(plugin: DB_hotkeys_plugin_t) -> Self {
-        let (abort_handle, abort_registration) = AbortHandle::new_pair();
-
         Self {
             plugin,
-            thread: None,
-            shortcut_handler: Arc::new(Mutex::new(ShortcutHandler::new(abort_registration))),
-            abort_handle: Arc::new(Mutex::new(Some(abort_handle))),
+            backend: None,
             commands: Vec::new(),
+            config: Vec::new(),
+            backend_config: String::new(),
+            local_sequence: SequenceState::default(),
+            global_sequence: SequenceState::default(),
+            held: ModeRunner::default(),
+            layers: LayerState::default(),
+            macros: MacroRunner::default(),
+            conflict_report: CString::default(),
+            grabbed_layer: None,
+            plugins_loaded: false,
         }
     }
 
@@ -61,80 +111,407 @@ impl MiscPlugin {
         tracing::debug!("plugin start");
 
         self.read_config();
+    }
 
-        self.thread = Some(PluginThread::new(self.shortcut_handler.clone()));
+    /// Hands out the running backend so the caller can stop it without holding
+    /// the plugin lock, backends dispatch through the plugin from their own threads.
+    pub fn take_backend(&mut self) -> Option<Box<dyn Backend>> {
+        // Nothing reports the releases anymore
+        self.held.clear();
+        self.backend.take()
     }
 
-    pub fn plugin_stop(&mut self) {
-        self.abort_handle
-            .lock_blocking()
-            .take()
-            .expect("Abort handle")
-            .abort();
+    /// Re-reads the `hotkey.*` items, for a new backend to be started with.
+    ///
+    /// Any previous backend must have been stopped through [`Self::take_backend`].
+    pub fn reset(&mut self) {
+        tracing::debug!("reset");
 
-        tracing::debug!("Waiting for shortcut handler to stop");
-        smol::block_on(async {
-            self.shortcut_handler.lock().await.stop().await;
-            tracing::debug!("Stopped session.");
-        });
+        self.commands.clear();
+        self.local_sequence.cancel();
+        self.global_sequence.cancel();
+        self.layers = LayerState::default();
+        self.macros.clear();
+        self.read_config();
+    }
 
+    /// First keystrokes of the global bindings in the default and the active
+    /// layer. Backends grab these and are asked to pass on whatever comes next
+    /// while a sequence is pending.
+    fn global_keys(&self) -> Vec<Keystroke> {
+        let mut keys: Vec<_> = self
+            .commands
+            .iter()
+            .filter(|x| x.isglobal != 0 && (x.layer.is_none() || x.layer == self.grabbed_layer))
+            .map(|x| x.sequence[0])
+            .collect();
+        keys.sort();
+        keys.dedup();
+        keys
+    }
 
-        if let Some(t) = self.thread.take() {
-            match t.join() {
-                Ok(_) => (),
-                Err(_) => {
-                    DeadBeef::log_detailed(DDB_LOG_LAYER_INFO, "Playback thread lingering!\n");
-                }
+    /// The keys to start a backend with. Backends are started without the
+    /// plugin lock, then handed over through [`Self::set_backend`].
+    pub fn backend_keys(&mut self) -> Vec<Keystroke> {
+        self.grabbed_layer = self.layers.active(Instant::now()).map(str::to_owned);
+        self.backend_config = DeadBeef::conf_get_str(backend::CONF_BACKEND, "auto");
+        self.global_keys()
+    }
+
+    /// Takes over a backend started with `keys`. Returns the one it replaces
+    /// for the caller to stop, if someone started a backend meanwhile.
+    pub fn set_backend(
+        &mut self,
+        backend: Option<Box<dyn Backend>>,
+        keys: &[Keystroke],
+    ) -> Option<Box<dyn Backend>> {
+        if let Some(backend) = &backend {
+            // The layer may have changed while it was starting
+            let current = self.global_keys();
+            if current != keys {
+                backend.set_keys(&current);
             }
         }
+        std::mem::replace(&mut self.backend, backend)
     }
 
     fn read_config(&mut self) {
-        for a in DeadBeef::conf_find_str("hotkey.").into_iter().flatten() {
-            if let Some(value) = a.value() {
-                match parse_line(value) {
-                    Ok((keystroke, isglobal, action_name, ctx)) => {
-                        tracing::debug!("keystroke: {keystroke}, isglobal: {isglobal}, action_name: {action_name}, ctx: {ctx}");
-                        if let Some((keycode, modifier)) = parse_shortcut(&keystroke) {
-                            let action = DeadBeef::find_action_by_name(&action_name);
-
-                            let new_command = Command {
-                                keycode,
-                                modifier,
-                                ctx,
-                                isglobal: isglobal as i32,
-                                action: action.map(|x| x.as_ptr()).unwrap_or(std::ptr::null_mut()),
-                            };
-                            tracing::debug!("new_command: {new_command:?}");
-                            self.commands.push(new_command);
+        self.config = hotkey_items();
+        let profiles = profiles::configured_profiles();
+        let profile = profiles::active_profile();
+        if !profile.is_empty() && !profiles.contains(&profile) {
+            tracing::warn!("Unknown hotkey profile \"{profile}\", using the default one");
+        }
+        let mut layer_names = Vec::new();
+
+        for (id, value) in &self.config {
+            match parse_line(value) {
+                Ok((keystroke, isglobal, action_name, ctx)) => {
+                    tracing::debug!("keystroke: {keystroke}, isglobal: {isglobal}, action_name: {action_name}, ctx: {ctx}");
+                    let parsed = split_layer(&keystroke).and_then(|(layer, keys)| {
+                        let (keys, condition) = split_condition(keys)?;
+                        let condition = condition.map(Condition::compile).transpose()?;
+                        Ok((layer, condition, Mode::split(keys)?))
+                    });
+                    let (layer, condition, (keys, mode)) = match parsed {
+                        Ok(x) => x,
+                        Err(msg) => {
+                            tracing::error!("Unable to parse hotkey config item: {msg}");
+                            continue;
+                        }
+                    };
+                    if !isglobal && mode != Mode::Press {
+                        tracing::warn!(
+                            "\"{keystroke}\": trigger modes only work for global hotkeys"
+                        );
+                    }
+                    if let Err(msg) = check_action(&action_name) {
+                        tracing::error!("Unable to parse hotkey config item: {msg}");
+                        continue;
+                    }
+                    let sequence = match parse_sequence(keys) {
+                        Ok(sequence) => sequence,
+                        Err(e) => {
+                            let msg = format!("{id}: unable to parse hotkey \"{keys}\": {e}");
+                            log_to_deadbeef(DDB_LOG_LAYER_DEFAULT, &msg);
+                            continue;
+                        }
+                    };
+                    // Resolved on first use, the action's plugin may not be loaded yet.
+                    let layer = layer.map(str::to_owned);
+                    if let Some(name) = &layer {
+                        if !layer_names.contains(name) {
+                            layer_names.push(name.clone());
                         }
                     }
-                    Err(msg) => tracing::error!("Unable to parse hotkey config item: {msg}"),
+                    let new_command = Command {
+                        id: id.clone(),
+                        sequence,
+                        mode,
+                        layer,
+                        condition,
+                        ctx,
+                        isglobal: isglobal as i32,
+                        action_name,
+                        action: None,
+                        warned: false,
+                    };
+                    tracing::debug!("new_command: {new_command:?}");
+                    self.commands.push(new_command);
                 }
+                Err(msg) => tracing::error!("Unable to parse hotkey config item: {msg}"),
             }
         }
+
+        self.check_conflicts();
+        self.check_reserved();
+
+        // Built-in actions and commands are looked up by name like any other,
+        // also when they are a step of a macro.
+        let bound: Vec<&str> = self
+            .commands
+            .iter()
+            .map(|c| c.action_name.as_str())
+            .collect();
+        let steps: Vec<macros::Step> = bound
+            .iter()
+            .filter_map(|name| parse_macro(name)?.ok())
+            .flatten()
+            .collect();
+        let names: Vec<&str> = bound
+            .iter()
+            .copied()
+            .chain(steps.iter().filter_map(macros::Step::action))
+            .collect();
+        let mut actions = layers::layer_actions(&layer_names);
+        actions.extend(macro_actions(bound.iter().copied()));
+        actions.extend(builtin_actions(names.iter().copied()));
+        let commands = command_actions(names.iter().copied());
+        if !commands.is_empty() && !exec::commands_enabled() {
+            let msg = format!(
+                "{} command bindings are disabled, set {CONF_COMMANDS_ENABLED} to 1 to run them",
+                commands.len()
+            );
+            log_to_deadbeef(DDB_LOG_LAYER_DEFAULT, &msg);
+        }
+        actions.extend(commands);
+        actions.extend(profile_actions(&profiles));
+        let changed = ACTIONS
+            .lock()
+            .is_ok_and(|mut a| a.set(&actions, run_action));
+        if changed {
+            DeadBeef::sendmessage(DB_EV_ACTIONSCHANGED, 0, 0, 0);
+        }
+        if self.plugins_loaded {
+            self.check_actions();
+        }
+    }
+
+    /// Reports the bindings whose action no plugin provides, once each.
+    fn check_actions(&mut self) {
+        for command in &mut self.commands {
+            command.action();
+        }
+    }
+
+    /// Checks the bound actions now that all plugins registered theirs, they
+    /// may not have while the config was first read.
+    pub fn plugins_loaded(&mut self) {
+        self.plugins_loaded = true;
+        self.check_actions();
+    }
+
+    /// Logs the bindings that shadow each other and keeps the report for
+    /// [`Self::conflict_report`].
+    fn check_conflicts(&mut self) {
+        let bindings: Vec<_> = self
+            .commands
+            .iter()
+            .map(|c| conflicts::Binding {
+                id: &c.id,
+                sequence: &c.sequence,
+                isglobal: c.isglobal != 0,
+                layer: c.layer.as_deref(),
+                condition: c.condition.as_ref().map(Condition::script),
+            })
+            .collect();
+        let found = find_conflicts(&bindings);
+        for conflict in &found {
+            log_to_deadbeef(DDB_LOG_LAYER_DEFAULT, &conflict.to_string());
+        }
+        self.conflict_report = CString::new(conflicts::report(&found)).unwrap_or_default();
+    }
+
+    /// Warns about bindings on keys the desktop likely uses itself.
+    fn check_reserved(&self) {
+        let desktop = std::env::var("XDG_CURRENT_DESKTOP").unwrap_or_default();
+        let desktops = Desktop::from_env(&desktop);
+        if desktops.is_empty() {
+            tracing::debug!("Unknown desktop \"{desktop}\", not checking for reserved shortcuts");
+            return;
+        }
+        let bindings: Vec<_> = self
+            .commands
+            .iter()
+            .map(|c| (c.id.as_str(), c.sequence[0]))
+            .collect();
+        for reserved in find_reserved(&bindings, &desktops) {
+            log_to_deadbeef(DDB_LOG_LAYER_DEFAULT, &reserved.to_string());
+        }
+    }
+
+    /// Conflicts between the bindings as of the last config read.
+    pub fn conflict_report(&self) -> &CStr {
+        &self.conflict_report
+    }
+
+    /// Whether the `hotkey.*` items or the backend setting differ from the
+    /// ones last read.
+    pub fn hotkeys_changed(&self) -> bool {
+        hotkey_items() != self.config
+            || DeadBeef::conf_get_str(backend::CONF_BACKEND, "auto") != self.backend_config
+    }
+
+    /// The backend capturing global hotkeys, if any.
+    pub fn backend_kind(&self) -> Option<backend::Kind> {
+        self.backend.as_ref().map(|b| b.kind())
+    }
+
+    /// Forgets every cached action pointer. Action lists are owned by their
+    /// plugins and may be rebuilt, so old pointers must not be kept around.
+    pub fn invalidate_actions(&mut self) {
+        for command in &mut self.commands {
+            command.action = None;
+        }
     }
 
     pub fn get_action_for_keycombo(
         &mut self,
-        key: i32,
-        mods: i32,
+        keystroke: Keystroke,
         isglobal: i32,
     ) -> Option<(ddb_action_context_t, *mut DB_plugin_action_t)> {
-        let act = self
+        let i = self.complete_sequence(keystroke.normalized(), isglobal)?;
+        let act = &mut self.commands[i];
+        act.action().map(|x| (act.ctx, x.as_ptr()))
+    }
+
+    /// Handles a global keystroke reported by a backend, returning the action
+    /// to run now, if any. The trigger mode of the binding decides when it
+    /// runs; timed ones run on their own.
+    pub fn global_key_event(&mut self, keystroke: Keystroke, event: KeyEvent) -> Option<Fire> {
+        let keystroke = keystroke.normalized();
+        if event == KeyEvent::Release {
+            return self.held.release(&keystroke);
+        }
+
+        let i = self.complete_sequence(keystroke, 1)?;
+        let act = &mut self.commands[i];
+        act.action()?;
+
+        let (action_name, ctx) = (act.action_name.clone(), act.ctx);
+        let fire: Fire = Arc::new(move || DeadBeef::call_action_by_name(&action_name, ctx));
+        self.held.press(keystroke, act.mode, fire, Instant::now())
+    }
+
+    /// Feeds a keystroke to the key sequence state, returning the index of
+    /// the command it completes.
+    ///
+    /// Bindings of the active layer come first, then the default layer's,
+    /// and within each those with a condition that holds come before those
+    /// without one.
+    fn complete_sequence(&mut self, keystroke: Keystroke, isglobal: i32) -> Option<usize> {
+        let now = Instant::now();
+        let layer = self.layers.active(now).map(str::to_owned);
+        let state = match isglobal {
+            0 => &mut self.local_sequence,
+            _ => &mut self.global_sequence,
+        };
+        let mut candidates: Vec<_> = self
             .commands
             .iter()
-            .find(|x| x.isglobal == isglobal && x.keycode == key && x.modifier == mods);
+            .enumerate()
+            .filter(|(_, x)| x.isglobal == isglobal)
+            .filter(|(_, x)| x.layer.is_none() || (layer.is_some() && x.layer == layer))
+            // Sequences without the keystroke can't be continued by it, no
+            // need to evaluate their conditions.
+            .filter(|(_, x)| x.sequence.contains(&keystroke))
+            .filter(|(_, x)| x.condition.as_ref().map_or(true, Condition::holds))
+            .collect();
+        candidates.sort_by_key(|(_, x)| (x.layer.is_none(), x.condition.is_none()));
+        let sequences = candidates.iter().map(|(i, x)| (*i, x.sequence.as_slice()));
 
-        act.map(|x| (x.ctx, x.action))
+        let Step::Complete(i) = state.feed(keystroke, now, sequence_timeout(), sequences) else {
+            return None;
+        };
+        self.layers.used(now);
+        self.sync_layer();
+        Some(i)
+    }
+
+    /// Runs a macro, or cancels it if it is still running.
+    pub fn toggle_macro(
+        &mut self,
+        name: &str,
+        steps: Vec<macros::Step>,
+        ctx: ddb_action_context_t,
+    ) {
+        self.macros.toggle(name, steps, move |action| {
+            DeadBeef::call_action_by_name(action, ctx)
+        });
+    }
+
+    /// Cancels all running macros.
+    pub fn cancel_macros(&mut self) {
+        self.macros.clear();
+    }
+
+    /// Switches to a binding layer, `None` for the default one.
+    pub fn switch_layer(&mut self, layer: Option<&str>, toggle: Toggle) {
+        let ms = DeadBeef::conf_get_int(CONF_LAYER_TIMEOUT, 0);
+        let timeout = (ms > 0).then(|| Duration::from_millis(ms as u64));
+        self.layers.switch(layer, toggle, timeout, Instant::now());
+        self.sync_layer();
+        self.schedule_layer_timeout();
+    }
+
+    /// Picks among global bindings sharing a keystroke by their layers, for
+    /// backends that can't tell them apart themselves.
+    pub fn pick_layer(&mut self, layers: &[Option<&str>]) -> Option<usize> {
+        let i = self.layers.pick(layers, Instant::now())?;
+        self.sync_layer();
+        Some(i)
+    }
+
+    /// Regrabs the backend's keys if the active layer changed, e.g. timed out.
+    pub fn sync_layer(&mut self) {
+        let active = self.layers.active(Instant::now()).map(str::to_owned);
+        if active == self.grabbed_layer {
+            return;
+        }
+        tracing::debug!("layer {:?} -> {active:?}", self.grabbed_layer);
+        self.grabbed_layer = active;
+        if let Some(backend) = &self.backend {
+            backend.set_keys(&self.global_keys());
+        }
+    }
+
+    /// Wakes up [`layer_timeout`] when the active layer times out, so its
+    /// keys get released even if no other key is pressed.
+    pub fn schedule_layer_timeout(&self) {
+        if let Some(deadline) = self.layers.deadline() {
+            smol::spawn(async move {
+                smol::Timer::at(deadline).await;
+                layer_timeout();
+            })
+            .detach();
+        }
+    }
+
+    /// When the global key sequence in progress times out, if there is one.
+    pub fn global_sequence_deadline(&self) -> Option<Instant> {
+        self.global_sequence.deadline()
     }
 }
 
-fn thread_main(plugin: Arc<Mutex<ShortcutHandler>>) {
-    smol::block_on(async {
-        tracing::debug!("Plugin thread received Start message");
-        if !plugin.lock().await.start_session().await.is_ok() {
-            tracing::error!("Plugin session failed to start");
+/// Checks the parameters of built-in actions and commands, and the steps of
+/// macros.
+fn check_action(action_name: &str) -> Result<(), String> {
+    let steps = match parse_macro(action_name) {
+        Some(steps) => steps?,
+        None => vec![macros::Step::Action(action_name.to_owned())],
+    };
+    for name in steps.iter().filter_map(macros::Step::action) {
+        if let Some(Err(msg)) = Builtin::parse(name) {
+            return Err(msg);
+        }
+        if let Some(Err(msg)) = parse_command(name) {
+            return Err(msg);
         }
-    });
+    }
+    Ok(())
+}
+
+fn sequence_timeout() -> Duration {
+    let ms = DeadBeef::conf_get_int(CONF_SEQUENCE_TIMEOUT, DEFAULT_SEQUENCE_TIMEOUT);
+    Duration::from_millis(ms.max(0) as u64)
 }
@@ -0,0 +1,195 @@
+use crate::utils::log_to_deadbeef;
+use deadbeef_sys::{DeadBeef, DB_EV_CONFIGCHANGED, DDB_LOG_LAYER_INFO};
+
+/// Config key with the name of the active profile, empty for the plain
+/// `hotkey.*` items.
+pub const CONF_PROFILE: &str = "hotkeys.profile";
+
+const ITEM_PREFIX: &str = "hotkey.";
+/// Items of the profile `desk` are `hotkey.profile.desk.*`.
+const PROFILE_PREFIX: &str = "hotkey.profile.";
+
+const NEXT_ACTION: &str = "hotkeys_next_profile";
+const DEFAULT_ACTION: &str = "hotkeys_default_profile";
+const SELECT_PREFIX: &str = "hotkeys_profile_";
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum ProfileAction<'a> {
+    /// Cycles through the default profile and the named ones.
+    Next,
+    /// Selects a profile, empty for the default one.
+    Select(&'a str),
+}
+
+/// The `(key, value)` config items with the bindings of the active profile.
+/// An unknown profile falls back to the default one.
+pub fn hotkey_items() -> Vec<(String, String)> {
+    let items = conf_items(ITEM_PREFIX);
+    let mut profile = active_profile();
+    if !profile_names(&items).contains(&profile) {
+        profile.clear();
+    }
+    profile_items(items, &profile)
+}
+
+pub fn active_profile() -> String {
+    DeadBeef::conf_get_str(CONF_PROFILE, "").trim().to_owned()
+}
+
+/// Names of the profiles in the config.
+pub fn configured_profiles() -> Vec<String> {
+    profile_names(&conf_items(PROFILE_PREFIX))
+}
+
+fn conf_items(prefix: &str) -> Vec<(String, String)> {
+    DeadBeef::conf_find_str(prefix)
+        .into_iter()
+        .flatten()
+        .filter_map(|a| Some((a.key()?.to_owned(), a.value()?.to_owned())))
+        .collect()
+}
+
+/// Names of the profiles having items, sorted.
+fn profile_names(items: &[(String, String)]) -> Vec<String> {
+    let mut names: Vec<String> = items
+        .iter()
+        .filter_map(|(key, _)| profile_of(key))
+        .map(str::to_owned)
+        .collect();
+    names.sort();
+    names.dedup();
+    names
+}
+
+/// Profile a `hotkey.*` key belongs to, `None` for the default one.
+fn profile_of(key: &str) -> Option<&str> {
+    let (name, _) = key.strip_prefix(PROFILE_PREFIX)?.split_once('.')?;
+    Some(name).filter(|name| !name.is_empty())
+}
+
+fn profile_items(items: Vec<(String, String)>, profile: &str) -> Vec<(String, String)> {
+    let profile = Some(profile).filter(|p| !p.is_empty());
+    items
+        .into_iter()
+        .filter(|(key, _)| profile_of(key) == profile)
+        .collect()
+}
+
+/// The profile after `current`, going from the default one through the named
+/// ones and back.
+fn next_profile<'a>(names: &'a [String], current: &str) -> &'a str {
+    match names.iter().position(|n| n == current) {
+        Some(i) if i + 1 < names.len() => &names[i + 1],
+        Some(_) => "",
+        None => names.first().map_or("", String::as_str),
+    }
+}
+
+/// Names and titles of the actions selecting profiles.
+pub fn profile_actions(names: &[String]) -> Vec<(String, String)> {
+    if names.is_empty() {
+        return Vec::new();
+    }
+    let mut actions = vec![
+        (NEXT_ACTION.to_owned(), "Hotkeys/Next profile".to_owned()),
+        (
+            DEFAULT_ACTION.to_owned(),
+            "Hotkeys/Default profile".to_owned(),
+        ),
+    ];
+    for name in names {
+        actions.push((
+            format!("{SELECT_PREFIX}{name}"),
+            format!("Hotkeys/Profile {name}"),
+        ));
+    }
+    actions
+}
+
+pub fn parse_profile_action(name: &str) -> Option<ProfileAction<'_>> {
+    match name {
+        NEXT_ACTION => Some(ProfileAction::Next),
+        DEFAULT_ACTION => Some(ProfileAction::Select("")),
+        _ => name.strip_prefix(SELECT_PREFIX).map(ProfileAction::Select),
+    }
+}
+
+/// Switches profiles. The bindings are reloaded, and the portal rebound, once
+/// DeadBeef passes on the config change.
+pub fn run_profile_action(action: ProfileAction) {
+    let profile = match action {
+        ProfileAction::Select(name) => name.to_owned(),
+        ProfileAction::Next => next_profile(&configured_profiles(), &active_profile()).to_owned(),
+    };
+
+    let msg = match profile.as_str() {
+        "" => "default profile".to_owned(),
+        name => format!("profile {name}"),
+    };
+    log_to_deadbeef(DDB_LOG_LAYER_INFO, &msg);
+    DeadBeef::conf_set_str(CONF_PROFILE, &profile);
+    DeadBeef::conf_save();
+    DeadBeef::sendmessage(DB_EV_CONFIGCHANGED, 0, 0, 0);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn items(keys: &[&str]) -> Vec<(String, String)> {
+        keys.iter()
+            .map(|k| (k.to_string(), String::new()))
+            .collect()
+    }
+
+    fn keys(items: Vec<(String, String)>) -> Vec<String> {
+        items.into_iter().map(|(k, _)| k).collect()
+    }
+
+    #[test]
+    fn splits_items_by_profile() {
+        let all = items(&[
+            "hotkey.key01",
+            "hotkey.profile.desk.key01",
+            "hotkey.profile.laptop.key01",
+            "hotkey.profile.desk.key02",
+            "hotkey.key02",
+        ]);
+        assert_eq!(profile_names(&all), ["desk", "laptop"]);
+        assert_eq!(
+            keys(profile_items(all.clone(), "")),
+            ["hotkey.key01", "hotkey.key02"]
+        );
+        assert_eq!(
+            keys(profile_items(all, "desk")),
+            ["hotkey.profile.desk.key01", "hotkey.profile.desk.key02"]
+        );
+    }
+
+    #[test]
+    fn cycles_profiles() {
+        let names = ["desk".to_owned(), "laptop".to_owned()];
+        assert_eq!(next_profile(&names, ""), "desk");
+        assert_eq!(next_profile(&names, "desk"), "laptop");
+        assert_eq!(next_profile(&names, "laptop"), "");
+        assert_eq!(next_profile(&[], ""), "");
+    }
+
+    #[test]
+    fn profile_action_names_round_trip() {
+        let actions = profile_actions(&["desk".to_owned()]);
+        let parsed: Vec<_> = actions
+            .iter()
+            .map(|(name, _)| parse_profile_action(name))
+            .collect();
+        assert_eq!(
+            parsed,
+            [
+                Some(ProfileAction::Next),
+                Some(ProfileAction::Select("")),
+                Some(ProfileAction::Select("desk")),
+            ]
+        );
+        assert_eq!(parse_profile_action("hotkeys_next_album"), None);
+    }
+}
@@ -0,0 +1,253 @@
+use crate::keystroke::Keystroke;
+use std::fmt;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Desktop {
+    Gnome,
+    Kde,
+    Sway,
+}
+
+impl Desktop {
+    pub fn name(self) -> &'static str {
+        match self {
+            Desktop::Gnome => "GNOME",
+            Desktop::Kde => "KDE",
+            Desktop::Sway => "sway",
+        }
+    }
+
+    /// The desktops named in `XDG_CURRENT_DESKTOP`, like `ubuntu:GNOME`.
+    pub fn from_env(value: &str) -> Vec<Desktop> {
+        let mut desktops = Vec::new();
+        for name in value.split(':') {
+            let desktop = match name.trim().to_ascii_lowercase().as_str() {
+                "gnome" | "gnome-classic" | "unity" => Desktop::Gnome,
+                "kde" => Desktop::Kde,
+                "sway" => Desktop::Sway,
+                _ => continue,
+            };
+            if !desktops.contains(&desktop) {
+                desktops.push(desktop);
+            }
+        }
+        desktops
+    }
+}
+
+/// Default shortcuts of desktops that take the keys before DeadBeef sees
+/// them, or that the portal refuses to bind. KDE's Meta is `Super`, and
+/// sway's `$mod` is `Super` in its default config.
+const RESERVED: &[(Desktop, &str, &str)] = &[
+    (Desktop::Gnome, "Super l", "lock screen"),
+    (Desktop::Gnome, "Ctrl Alt t", "open a terminal"),
+    (Desktop::Gnome, "Ctrl Alt Delete", "log out"),
+    (Desktop::Gnome, "Super a", "show all apps"),
+    (Desktop::Gnome, "Super d", "hide all windows"),
+    (Desktop::Gnome, "Super h", "hide window"),
+    (Desktop::Gnome, "Super m", "show the notification list"),
+    (Desktop::Gnome, "Super v", "show the notification list"),
+    (Desktop::Gnome, "Super s", "show quick settings"),
+    (Desktop::Gnome, "Super space", "switch input source"),
+    (Desktop::Gnome, "Super Tab", "switch applications"),
+    (Desktop::Gnome, "Alt Tab", "switch applications"),
+    (Desktop::Gnome, "Super Up", "maximize window"),
+    (Desktop::Gnome, "Super Down", "restore window"),
+    (Desktop::Gnome, "Super Left", "tile window left"),
+    (Desktop::Gnome, "Super Right", "tile window right"),
+    (
+        Desktop::Gnome,
+        "Super Page_Up",
+        "switch to the workspace above",
+    ),
+    (
+        Desktop::Gnome,
+        "Super Page_Down",
+        "switch to the workspace below",
+    ),
+    (Desktop::Gnome, "Alt F2", "run a command"),
+    (Desktop::Gnome, "Alt F4", "close window"),
+    (Desktop::Gnome, "Print", "take a screenshot"),
+    (Desktop::Kde, "Super l", "lock session"),
+    (Desktop::Kde, "Ctrl Alt l", "lock session"),
+    (Desktop::Kde, "Ctrl Alt t", "open Konsole"),
+    (Desktop::Kde, "Ctrl Alt Delete", "log out"),
+    (Desktop::Kde, "Ctrl Alt Escape", "kill a window"),
+    (Desktop::Kde, "Ctrl Escape", "show System Monitor"),
+    (Desktop::Kde, "Alt space", "open KRunner"),
+    (Desktop::Kde, "Alt F2", "open KRunner"),
+    (Desktop::Kde, "Alt F4", "close window"),
+    (Desktop::Kde, "Alt Tab", "walk through windows"),
+    (Desktop::Kde, "Super d", "peek at desktop"),
+    (Desktop::Kde, "Super e", "open Dolphin"),
+    (Desktop::Kde, "Super v", "show clipboard items"),
+    (Desktop::Kde, "Ctrl F1", "switch to desktop 1"),
+    (Desktop::Kde, "Ctrl F2", "switch to desktop 2"),
+    (Desktop::Kde, "Ctrl F3", "switch to desktop 3"),
+    (Desktop::Kde, "Ctrl F4", "switch to desktop 4"),
+    (Desktop::Kde, "Print", "open Spectacle"),
+    (Desktop::Sway, "Super Return", "open a terminal"),
+    (Desktop::Sway, "Super d", "open the launcher"),
+    (Desktop::Sway, "Super Shift q", "kill window"),
+    (Desktop::Sway, "Super Shift c", "reload the config"),
+    (Desktop::Sway, "Super Shift e", "exit sway"),
+    (Desktop::Sway, "Super h", "focus left"),
+    (Desktop::Sway, "Super j", "focus down"),
+    (Desktop::Sway, "Super k", "focus up"),
+    (Desktop::Sway, "Super l", "focus right"),
+    (Desktop::Sway, "Super Left", "focus left"),
+    (Desktop::Sway, "Super Down", "focus down"),
+    (Desktop::Sway, "Super Up", "focus up"),
+    (Desktop::Sway, "Super Right", "focus right"),
+    (Desktop::Sway, "Super Shift h", "move window left"),
+    (Desktop::Sway, "Super Shift j", "move window down"),
+    (Desktop::Sway, "Super Shift k", "move window up"),
+    (Desktop::Sway, "Super Shift l", "move window right"),
+    (Desktop::Sway, "Super 1", "switch to workspace 1"),
+    (Desktop::Sway, "Super 2", "switch to workspace 2"),
+    (Desktop::Sway, "Super 3", "switch to workspace 3"),
+    (Desktop::Sway, "Super 4", "switch to workspace 4"),
+    (Desktop::Sway, "Super 5", "switch to workspace 5"),
+    (Desktop::Sway, "Super 6", "switch to workspace 6"),
+    (Desktop::Sway, "Super 7", "switch to workspace 7"),
+    (Desktop::Sway, "Super 8", "switch to workspace 8"),
+    (Desktop::Sway, "Super 9", "switch to workspace 9"),
+    (Desktop::Sway, "Super 0", "switch to workspace 10"),
+    (Desktop::Sway, "Super b", "split horizontally"),
+    (Desktop::Sway, "Super v", "split vertically"),
+    (Desktop::Sway, "Super s", "stacking layout"),
+    (Desktop::Sway, "Super w", "tabbed layout"),
+    (Desktop::Sway, "Super e", "toggle split layout"),
+    (Desktop::Sway, "Super f", "fullscreen"),
+    (Desktop::Sway, "Super a", "focus parent"),
+    (Desktop::Sway, "Super r", "resize mode"),
+    (
+        Desktop::Sway,
+        "Super space",
+        "toggle focus between tiling and floating",
+    ),
+    (Desktop::Sway, "Super Shift space", "toggle floating"),
+    (Desktop::Sway, "Super minus", "show the scratchpad"),
+    (
+        Desktop::Sway,
+        "Super Shift minus",
+        "move window to the scratchpad",
+    ),
+];
+
+/// A binding on keys a desktop uses by default.
+#[derive(Debug, PartialEq, Eq)]
+pub struct Reserved {
+    /// Config key of the binding.
+    pub id: String,
+    pub desktop: Desktop,
+    pub keys: &'static str,
+    pub function: &'static str,
+}
+
+impl fmt::Display for Reserved {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{}: {} is likely taken by {} to {}",
+            self.id,
+            self.keys,
+            self.desktop.name(),
+            self.function
+        )
+    }
+}
+
+/// Checks the first keystroke of each binding, given by config key, against
+/// the default shortcuts of `desktops`.
+pub fn find_reserved(bindings: &[(&str, Keystroke)], desktops: &[Desktop]) -> Vec<Reserved> {
+    let reserved: Vec<_> = RESERVED
+        .iter()
+        .filter(|(desktop, _, _)| desktops.contains(desktop))
+        .filter_map(|&(desktop, keys, function)| {
+            Some((
+                keys.parse::<Keystroke>().ok()?.normalized(),
+                desktop,
+                keys,
+                function,
+            ))
+        })
+        .collect();
+
+    let mut found = Vec::new();
+    for &(id, stroke) in bindings {
+        let stroke = stroke.normalized();
+        for &(r, desktop, keys, function) in &reserved {
+            if r == stroke {
+                found.push(Reserved {
+                    id: id.to_owned(),
+                    desktop,
+                    keys,
+                    function,
+                });
+            }
+        }
+    }
+    found
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::keystroke::Modifiers;
+
+    fn keystroke(keys: &str) -> Keystroke {
+        keys.parse::<Keystroke>().unwrap().normalized()
+    }
+
+    #[test]
+    fn reserved_shortcuts_parse() {
+        for (_, keys, _) in RESERVED {
+            assert!(keys.parse::<Keystroke>().is_ok(), "{keys:?}");
+        }
+    }
+
+    #[test]
+    fn detects_desktops() {
+        assert_eq!(Desktop::from_env("ubuntu:GNOME"), [Desktop::Gnome]);
+        assert_eq!(Desktop::from_env("KDE"), [Desktop::Kde]);
+        assert_eq!(Desktop::from_env("sway"), [Desktop::Sway]);
+        assert_eq!(Desktop::from_env("XFCE"), []);
+    }
+
+    #[test]
+    fn finds_reserved_shortcuts() {
+        let bindings = [
+            ("hotkey.key01", keystroke("Super l")),
+            ("hotkey.key02", keystroke("Ctrl Alt t")),
+            ("hotkey.key03", keystroke("Super p")),
+            // Shift l, not the l GNOME locks the screen with
+            ("hotkey.key04", keystroke("Super L")),
+        ];
+        let found: Vec<_> = find_reserved(&bindings, &[Desktop::Gnome])
+            .iter()
+            .map(ToString::to_string)
+            .collect();
+        assert_eq!(
+            found,
+            [
+                "hotkey.key01: Super l is likely taken by GNOME to lock screen",
+                "hotkey.key02: Ctrl Alt t is likely taken by GNOME to open a terminal",
+            ]
+        );
+
+        let all = [Desktop::Gnome, Desktop::Kde, Desktop::Sway];
+        let found = find_reserved(&bindings[..1], &all);
+        let desktops: Vec<_> = found.iter().map(|r| r.desktop).collect();
+        assert_eq!(desktops, all);
+        // As reported with NumLock on
+        let num_lock = Keystroke::new(bindings[0].1.keysym, Modifiers::SUPER | Modifiers::NUM_LOCK);
+        assert_eq!(
+            find_reserved(&[("hotkey.key05", num_lock)], &[Desktop::Gnome]).len(),
+            1
+        );
+
+        // Unknown desktops aren't guessed at
+        assert_eq!(find_reserved(&bindings, &[]), []);
+    }
+}
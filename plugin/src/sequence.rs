@@ -0,0 +1,192 @@
+use crate::{keystroke::Keystroke, keysyms::is_modifier_keysym};
+use std::time::{Duration, Instant};
+
+/// Config key for how long to wait for the next keystroke of a sequence, in ms.
+pub const CONF_SEQUENCE_TIMEOUT: &str = "hotkeys.sequence_timeout";
+pub const DEFAULT_SEQUENCE_TIMEOUT: i32 = 1000;
+
+const ESCAPE: i32 = 0xff1b;
+
+/// What a keystroke fed to a [`SequenceState`] amounts to.
+#[derive(Debug, PartialEq, Eq)]
+pub enum Step {
+    /// It completes the sequence with this index.
+    Complete(usize),
+    /// It starts or continues a sequence, the next keystroke decides.
+    Pending,
+    /// Nothing is bound to it.
+    None,
+}
+
+/// Keystrokes typed so far towards a multi-stroke binding like `Ctrl x, n`.
+#[derive(Debug, Default)]
+pub struct SequenceState {
+    prefix: Vec<Keystroke>,
+    deadline: Option<Instant>,
+}
+
+impl SequenceState {
+    /// Feeds a keystroke, matching it against `sequences` given with their
+    /// indexes.
+    ///
+    /// An exact match wins over longer sequences starting the same way.
+    /// Escape cancels a pending sequence, and any other key that doesn't
+    /// continue it cancels it and is looked up on its own.
+    pub fn feed<'a, I>(
+        &mut self,
+        stroke: Keystroke,
+        now: Instant,
+        timeout: Duration,
+        sequences: I,
+    ) -> Step
+    where
+        I: Iterator<Item = (usize, &'a [Keystroke])> + Clone,
+    {
+        if self.deadline.is_some_and(|d| now >= d) {
+            self.cancel();
+        }
+
+        // Holding down Ctrl for the next stroke shouldn't count as one.
+        if is_modifier_keysym(stroke.keysym) {
+            return if self.prefix.is_empty() {
+                Step::None
+            } else {
+                Step::Pending
+            };
+        }
+
+        if !self.prefix.is_empty() && stroke.keysym == ESCAPE {
+            self.cancel();
+            return Step::None;
+        }
+
+        let continuing = !self.prefix.is_empty();
+        self.prefix.push(stroke);
+        let mut step = self.lookup(sequences.clone());
+        if step == Step::None && continuing {
+            self.prefix.clear();
+            self.prefix.push(stroke);
+            step = self.lookup(sequences);
+        }
+
+        match step {
+            Step::Pending => self.deadline = Some(now + timeout),
+            _ => self.cancel(),
+        }
+        step
+    }
+
+    fn lookup<'a>(&self, sequences: impl Iterator<Item = (usize, &'a [Keystroke])>) -> Step {
+        let mut step = Step::None;
+        for (i, sequence) in sequences {
+            if sequence == self.prefix.as_slice() {
+                return Step::Complete(i);
+            }
+            if sequence.starts_with(&self.prefix) {
+                step = Step::Pending;
+            }
+        }
+        step
+    }
+
+    pub fn cancel(&mut self) {
+        self.prefix.clear();
+        self.deadline = None;
+    }
+
+    /// When the pending sequence times out, `None` if there is none.
+    pub fn deadline(&self) -> Option<Instant> {
+        self.deadline
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::keystroke::Modifiers;
+
+    const CTRL_X: Keystroke = Keystroke::new(0x78, Modifiers::CONTROL);
+    const N: Keystroke = Keystroke::from_raw(0x6e, 0);
+    const P: Keystroke = Keystroke::from_raw(0x70, 0);
+    const Q: Keystroke = Keystroke::from_raw(0x71, 0);
+    const TIMEOUT: Duration = Duration::from_millis(1000);
+
+    fn bindings() -> Vec<Vec<Keystroke>> {
+        vec![
+            vec![CTRL_X, N],
+            vec![CTRL_X, P],
+            vec![P],
+            vec![CTRL_X, CTRL_X, N],
+            vec![Q],
+        ]
+    }
+
+    fn feed(state: &mut SequenceState, stroke: Keystroke, now: Instant) -> Step {
+        let bindings = bindings();
+        state.feed(
+            stroke,
+            now,
+            TIMEOUT,
+            bindings.iter().map(Vec::as_slice).enumerate(),
+        )
+    }
+
+    #[test]
+    fn completes_sequences() {
+        let now = Instant::now();
+        let mut state = SequenceState::default();
+
+        assert_eq!(feed(&mut state, CTRL_X, now), Step::Pending);
+        assert_eq!(state.deadline(), Some(now + TIMEOUT));
+        // Releasing and pressing Ctrl again in between doesn't matter
+        assert_eq!(
+            feed(&mut state, Keystroke::new(0xffe3, Modifiers::CONTROL), now),
+            Step::Pending
+        );
+        assert_eq!(feed(&mut state, N, now), Step::Complete(0));
+        assert_eq!(state.deadline(), None);
+
+        assert_eq!(feed(&mut state, CTRL_X, now), Step::Pending);
+        assert_eq!(feed(&mut state, CTRL_X, now), Step::Pending);
+        assert_eq!(feed(&mut state, N, now), Step::Complete(3));
+
+        assert_eq!(feed(&mut state, P, now), Step::Complete(2));
+        assert_eq!(feed(&mut state, N, now), Step::None);
+    }
+
+    #[test]
+    fn times_out() {
+        let now = Instant::now();
+        let mut state = SequenceState::default();
+
+        assert_eq!(feed(&mut state, CTRL_X, now), Step::Pending);
+        assert_eq!(feed(&mut state, N, now + TIMEOUT), Step::None);
+        assert_eq!(feed(&mut state, CTRL_X, now), Step::Pending);
+        // A plain p after the timeout is the single-stroke binding
+        assert_eq!(feed(&mut state, P, now + TIMEOUT * 2), Step::Complete(2));
+    }
+
+    #[test]
+    fn escape_and_unrelated_keys_cancel() {
+        let now = Instant::now();
+        let mut state = SequenceState::default();
+
+        assert_eq!(feed(&mut state, CTRL_X, now), Step::Pending);
+        assert_eq!(
+            feed(&mut state, Keystroke::from_raw(ESCAPE, 0), now),
+            Step::None
+        );
+        assert_eq!(feed(&mut state, N, now), Step::None);
+
+        assert_eq!(feed(&mut state, CTRL_X, now), Step::Pending);
+        assert_eq!(
+            feed(&mut state, Keystroke::new(0x6e, Modifiers::SHIFT), now),
+            Step::None
+        );
+        assert_eq!(state.deadline(), None);
+
+        // The unrelated key still counts on its own
+        assert_eq!(feed(&mut state, CTRL_X, now), Step::Pending);
+        assert_eq!(feed(&mut state, Q, now), Step::Complete(4));
+    }
+}
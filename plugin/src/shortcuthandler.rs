@@ -1,4 +1,15 @@
-use crate::{utils::last_segment_after_unescaped_slash, utils::parse_line};
+use crate::{
+    backend::{Backend, Kind},
+    condition::{join_condition, split_condition, Condition},
+    keystroke::parse_sequence,
+    layers::split_layer,
+    modes::{Fire, Mode, ModeRunner},
+    profiles::hotkey_items,
+    trigger::{to_keystroke, to_xdg_trigger},
+    utils::{
+        format_line, hash_lines, last_segment_after_unescaped_slash, log_to_deadbeef, parse_line,
+    },
+};
 use ashpd::desktop::{
     global_shortcuts::{
         Activated, Deactivated, GlobalShortcuts, NewShortcut, Shortcut, ShortcutsChanged,
@@ -6,12 +17,23 @@ use ashpd::desktop::{
     ResponseError, Session,
 };
 use async_lock::Mutex;
-use deadbeef_sys::DeadBeef;
+use deadbeef_sys::{ddb_action_context_t, DeadBeef, DDB_LOG_LAYER_INFO};
 use futures_util::{
-    future::Abortable,
+    future::{AbortHandle, Abortable},
     stream::{select_all, AbortRegistration, Stream, StreamExt},
 };
-use std::{collections::HashSet, str::FromStr, sync::Arc};
+use std::{
+    collections::{HashMap, HashSet},
+    str::FromStr,
+    sync::Arc,
+    thread,
+    time::Instant,
+};
+
+/// When set, triggers assigned by the desktop are written back to `hotkey.*`.
+const CONF_STORE_TRIGGERS: &str = "hotkeys.store_portal_triggers";
+/// Hash of the shortcuts last passed to `BindShortcuts`.
+const CONF_BOUND_HASH: &str = "hotkeys.portal_bound_hash";
 
 #[derive(Debug, Clone)]
 struct RegisteredShortcut {
@@ -19,6 +41,16 @@ struct RegisteredShortcut {
     activation: String,
 }
 
+/// What to do when a portal shortcut is activated.
+#[derive(Debug, Clone)]
+struct Binding {
+    action_name: String,
+    ctx: ddb_action_context_t,
+    mode: Mode,
+    layer: Option<String>,
+    condition: Option<Condition>,
+}
+
 #[derive(Debug)]
 enum Event {
     Activated(Activated),
@@ -26,11 +58,71 @@ enum Event {
     ShortcutsChanged(ShortcutsChanged),
 }
 
+/// Global hotkeys through the XDG GlobalShortcuts portal.
+pub struct PortalBackend {
+    handle: thread::JoinHandle<()>,
+    shortcut_handler: Arc<Mutex<ShortcutHandler>>,
+    abort_handle: AbortHandle,
+}
+
+impl PortalBackend {
+    pub fn new() -> Self {
+        let (abort_handle, abort_registration) = AbortHandle::new_pair();
+        let shortcut_handler = Arc::new(Mutex::new(ShortcutHandler::new(abort_registration)));
+        let plugin = shortcut_handler.clone();
+
+        Self {
+            handle: thread::spawn(move || thread_main(plugin)),
+            shortcut_handler,
+            abort_handle,
+        }
+    }
+
+    /// Checks that the desktop provides the GlobalShortcuts portal.
+    pub fn probe() -> ashpd::Result<()> {
+        smol::block_on(GlobalShortcuts::new()).map(|_| ())
+    }
+}
+
+impl Backend for PortalBackend {
+    fn kind(&self) -> Kind {
+        Kind::Portal
+    }
+
+    /// Closes the portal session and waits for the worker thread to exit.
+    fn stop(self: Box<Self>) {
+        self.abort_handle.abort();
+
+        tracing::debug!("Waiting for shortcut handler to stop");
+        smol::block_on(async {
+            self.shortcut_handler.lock().await.stop().await;
+            tracing::debug!("Stopped session.");
+        });
+
+        if self.handle.join().is_err() {
+            DeadBeef::log_detailed(DDB_LOG_LAYER_INFO, "Playback thread lingering!\n");
+        }
+    }
+}
+
+fn thread_main(plugin: Arc<Mutex<ShortcutHandler>>) {
+    smol::block_on(async {
+        tracing::debug!("Plugin thread received Start message");
+        if plugin.lock().await.start_session().await.is_err() {
+            tracing::error!("Plugin session failed to start");
+        }
+    });
+}
+
 pub(crate) struct ShortcutHandler {
     session: Arc<Mutex<Option<Session<'static, GlobalShortcuts<'static>>>>>,
     abort_registration: std::cell::Cell<Option<AbortRegistration>>,
     triggers: Arc<Mutex<Vec<RegisteredShortcut>>>,
     activations: Arc<Mutex<HashSet<String>>>,
+    /// Bindings by shortcut id. Bindings of other layers with the same
+    /// keystroke share the shortcut, the active layer picks one.
+    bindings: Arc<Mutex<HashMap<String, Vec<Binding>>>>,
+    held: Arc<Mutex<ModeRunner<String>>>,
 }
 
 impl ShortcutHandler {
@@ -40,42 +132,92 @@ impl ShortcutHandler {
             abort_registration: std::cell::Cell::new(Some(abort_registration)),
             triggers: Default::default(),
             activations: Default::default(),
+            bindings: Default::default(),
+            held: Default::default(),
         }
     }
 
     pub async fn start_session(&self) -> ashpd::Result<()> {
         // Collect shortcuts from configuration entries `hotkey.*`.
         // Each value should parse as: `"<keystroke>" <num1> <num2> <action name...>`
+        // The config key (`hotkey.key07`) doubles as the shortcut id, so several
+        // triggers can be bound to the same action.
         let mut collected: Vec<_> = Vec::new();
-        for a in DeadBeef::conf_find_str("hotkey.").into_iter().flatten() {
-            if let Some(value) = a.value() {
-                match parse_line(value) {
-                    Ok((keystroke, global, action_name, _)) => {
-                        if !global {
-                            // skip non-global bindings for portal registration
+        let mut collected_lines: Vec<String> = Vec::new();
+        let mut bindings: HashMap<String, Vec<Binding>> = HashMap::new();
+        let mut registered = HashMap::new();
+        for (id, value) in &hotkey_items() {
+            match parse_line(value) {
+                Ok((keystroke, global, action_name, ctx)) => {
+                    if !global {
+                        // skip non-global bindings for portal registration
+                        continue;
+                    }
+                    let parsed = split_layer(&keystroke).and_then(|(layer, keys)| {
+                        let (keys, condition) = split_condition(keys)?;
+                        let condition = condition.map(Condition::compile).transpose()?;
+                        Ok((layer, condition, Mode::split(keys)?))
+                    });
+                    let (layer, condition, (keystroke, mode)) = match parsed {
+                        Ok(x) => x,
+                        Err(msg) => {
+                            tracing::error!("Unable to parse hotkey config item: {msg}");
                             continue;
                         }
+                    };
+                    let binding = Binding {
+                        action_name,
+                        ctx,
+                        mode,
+                        layer: layer.map(str::to_owned),
+                        condition,
+                    };
 
-                        // Use the action title if available, otherwise fall back to the action name
+                    let strokes = parse_sequence(keystroke).ok();
+                    if let Some(shared) = strokes.as_ref().and_then(|s| registered.get(s)) {
+                        bindings
+                            .entry(String::clone(shared))
+                            .or_default()
+                            .push(binding);
+                        continue;
+                    }
+                    if let Some(strokes) = strokes {
+                        registered.insert(strokes, id.to_owned());
+                    }
 
-                        let raw_title = DeadBeef::find_action_by_name(&action_name)
-                            .and_then(|act| act.title().map(|s| s.to_string()))
-                            .unwrap_or_else(|| action_name.clone());
+                    let action_name = &binding.action_name;
+                    // Use the action title if available, otherwise fall back to the action name
 
-                        let title_segment = last_segment_after_unescaped_slash(&raw_title);
+                    let raw_title = DeadBeef::find_action_by_name(action_name)
+                        .and_then(|act| act.title().map(|s| s.to_string()))
+                        .unwrap_or_else(|| action_name.clone());
 
-                        // Convert escaped forward slashes ("\/" -> "/") in the final segment
-                        let title = title_segment.replace("\\/", "/");
+                    let title_segment = last_segment_after_unescaped_slash(&raw_title);
 
-                        tracing::debug!("{keystroke} = {}", title);
+                    // Convert escaped forward slashes ("\/" -> "/") in the final segment
+                    let title = title_segment.replace("\\/", "/");
 
-                        collected.push(
-                            NewShortcut::new(action_name.as_str(), title.as_str())
-                                .preferred_trigger(keystroke.as_str()),
+                    let trigger = to_xdg_trigger(keystroke);
+                    if keystroke.contains(',') {
+                        // The portal only knows single keystrokes, leave it to the desktop.
+                        let msg = format!(
+                            "key sequence \"{keystroke}\" can't be bound through the portal, \
+                             assign a shortcut in the desktop settings"
                         );
+                        log_to_deadbeef(DDB_LOG_LAYER_INFO, &msg);
+                    } else if trigger.is_none() {
+                        tracing::warn!("No portal trigger for keystroke \"{keystroke}\"");
                     }
-                    Err(msg) => tracing::error!("Unable to parse hotkey config item: {msg}"),
+
+                    tracing::debug!("{keystroke} ({trigger:?}) = {}", title);
+
+                    collected.push(
+                        NewShortcut::new(id, title.as_str()).preferred_trigger(trigger.as_deref()),
+                    );
+                    collected_lines.push(format!("{id}\t{title}\t{trigger:?}"));
+                    bindings.entry(id.to_owned()).or_default().push(binding);
                 }
+                Err(msg) => tracing::error!("Unable to parse hotkey config item: {msg}"),
             }
         }
 
@@ -86,18 +228,24 @@ impl ShortcutHandler {
             Some(collected)
         };
 
+        *self.bindings.lock().await = bindings;
+
         // Set Application id
-        let appid = ashpd::AppID::from_str(&"music.deadbeef.player")?;
+        let appid = ashpd::AppID::from_str("music.deadbeef.player")?;
         ashpd::register_host_app(appid).await?;
 
         match shortcuts {
             Some(shortcuts) => {
                 let global_shortcuts = GlobalShortcuts::new().await?;
                 let session = global_shortcuts.create_session().await?;
-                let request = global_shortcuts
-                    .bind_shortcuts(&session, &shortcuts[..], None)
-                    .await?;
-                let response = request.response();
+                let response = self
+                    .restore_or_bind(
+                        &global_shortcuts,
+                        &session,
+                        &shortcuts[..],
+                        &hash_lines(&collected_lines),
+                    )
+                    .await;
                 if let Err(e) = &response {
                     match e {
                         ashpd::Error::Response(ResponseError::Cancelled) => {
@@ -113,7 +261,6 @@ impl ShortcutHandler {
                 match response {
                     Ok(resp) => {
                         let triggers: Vec<_> = resp
-                            .shortcuts()
                             .iter()
                             .map(|s: &Shortcut| RegisteredShortcut {
                                 id: s.id().to_owned(),
@@ -121,6 +268,7 @@ impl ShortcutHandler {
                             })
                             .collect();
                         *self.triggers.lock().await = triggers;
+                        store_triggers(&resp);
                         self.session.lock().await.replace(session);
                         loop {
                             if self.session.lock().await.is_none() {
@@ -153,6 +301,42 @@ impl ShortcutHandler {
         Ok(())
     }
 
+    /// Reuses the shortcuts the desktop kept from an earlier session when they
+    /// match the configuration. Binding makes some desktops ask the user for
+    /// confirmation, so only do it when the configured set changed.
+    async fn restore_or_bind(
+        &self,
+        global_shortcuts: &GlobalShortcuts<'_>,
+        session: &Session<'_, GlobalShortcuts<'_>>,
+        shortcuts: &[NewShortcut],
+        hash: &str,
+    ) -> ashpd::Result<Vec<Shortcut>> {
+        match global_shortcuts.list_shortcuts(session).await?.response() {
+            Ok(listed) => {
+                let listed_ids: HashSet<&str> =
+                    listed.shortcuts().iter().map(Shortcut::id).collect();
+                let bindings = self.bindings.lock().await;
+                let bound_ids: HashSet<&str> = bindings.keys().map(String::as_str).collect();
+
+                if listed_ids == bound_ids && DeadBeef::conf_get_str(CONF_BOUND_HASH, "") == hash {
+                    tracing::debug!("Shortcuts unchanged since last bind, not binding again");
+                    return Ok(listed.shortcuts().to_vec());
+                }
+            }
+            Err(e) => tracing::debug!("Unable to list shortcuts: {e}"),
+        }
+
+        let response = global_shortcuts
+            .bind_shortcuts(session, shortcuts, None)
+            .await?
+            .response()?;
+
+        DeadBeef::conf_set_str(CONF_BOUND_HASH, hash);
+        DeadBeef::conf_save();
+
+        Ok(response.shortcuts().to_vec())
+    }
+
     async fn track_incoming_events(&self, global_shortcuts: &GlobalShortcuts<'_>) {
         let Ok(activated_stream) = global_shortcuts.receive_activated().await else {
             return;
@@ -199,6 +383,8 @@ impl ShortcutHandler {
         }
         self.activations.lock().await.clear();
         self.triggers.lock().await.clear();
+        self.bindings.lock().await.clear();
+        self.held.lock().await.clear();
     }
 
     async fn display_activations(&self) {
@@ -224,7 +410,39 @@ impl ShortcutHandler {
         {
             let mut activations = self.activations.lock().await;
             activations.insert(activation.shortcut_id().into());
-            DeadBeef::call_action_by_name(activation.shortcut_id());
+        }
+
+        let mut candidates = self
+            .bindings
+            .lock()
+            .await
+            .get(activation.shortcut_id())
+            .cloned()
+            .unwrap_or_default();
+        // Those with a condition that holds win over those without one.
+        candidates.retain(|b| b.condition.as_ref().map_or(true, Condition::holds));
+        candidates.sort_by_key(|b| b.condition.is_none());
+        let layers: Vec<_> = candidates.iter().map(|b| b.layer.as_deref()).collect();
+        let binding = if candidates.is_empty() {
+            None
+        } else {
+            crate::pick_layer(&layers).map(|i| candidates[i].clone())
+        };
+        match binding {
+            Some(Binding {
+                action_name,
+                ctx,
+                mode,
+                ..
+            }) => {
+                let fire: Fire = Arc::new(move || DeadBeef::call_action_by_name(&action_name, ctx));
+                let id = activation.shortcut_id().to_owned();
+                let now = self.held.lock().await.press(id, mode, fire, Instant::now());
+                if let Some(fire) = now {
+                    fire();
+                }
+            }
+            None => tracing::debug!("No binding for shortcut in this layer: {activation:?}"),
         }
 
         self.display_activations().await
@@ -239,6 +457,15 @@ impl ShortcutHandler {
                 );
             }
         }
+
+        let now = self
+            .held
+            .lock()
+            .await
+            .release(&deactivation.shortcut_id().to_owned());
+        if let Some(fire) = now {
+            fire();
+        }
         self.display_activations().await
     }
 
@@ -251,7 +478,60 @@ impl ShortcutHandler {
                 activation: s.trigger_description().to_owned(),
             })
             .collect();
+        store_triggers(change.shortcuts());
 
         self.display_activations().await
     }
 }
+
+/// Writes the triggers the desktop actually assigned back into the `hotkey.*`
+/// items, so DeadBeef's preferences show what is bound.
+fn store_triggers(shortcuts: &[Shortcut]) {
+    if DeadBeef::conf_get_int(CONF_STORE_TRIGGERS, 0) == 0 {
+        return;
+    }
+
+    let mut changed = false;
+    for s in shortcuts {
+        let Some(keystroke) = to_keystroke(s.trigger_description()) else {
+            tracing::debug!(
+                "Unknown trigger {:?} for {}",
+                s.trigger_description(),
+                s.id()
+            );
+            continue;
+        };
+        let line = DeadBeef::conf_get_str(s.id(), "");
+        let Ok((old_keystroke, isglobal, action_name, ctx)) = parse_line(&line) else {
+            continue;
+        };
+        let Ok((layer, old_keystroke)) = split_layer(&old_keystroke) else {
+            continue;
+        };
+        let Ok((old_keystroke, condition)) = split_condition(old_keystroke) else {
+            continue;
+        };
+        let Ok((old_keystroke, mode)) = Mode::split(old_keystroke) else {
+            continue;
+        };
+        // A key sequence stays, the desktop's trigger only stands in for it.
+        if old_keystroke.contains(',')
+            || parse_sequence(old_keystroke).ok() == parse_sequence(&keystroke).ok()
+        {
+            continue;
+        }
+
+        tracing::debug!("{}: {old_keystroke} -> {keystroke}", s.id());
+        let keystroke = join_condition(&mode.join(&keystroke), condition);
+        let keystroke = match layer {
+            Some(layer) => format!("[{layer}] {keystroke}"),
+            None => keystroke,
+        };
+        DeadBeef::conf_set_str(s.id(), format_line(&keystroke, isglobal, &action_name, ctx));
+        changed = true;
+    }
+
+    if changed {
+        DeadBeef::conf_save();
+    }
+}
@@ -0,0 +1,265 @@
+use crate::{
+    keystroke::{Keystroke, Modifiers},
+    keysyms::{keysym_from_str, keysym_name_ignore_case, name_from_keysym},
+};
+
+/// Modifier names used by the XDG shortcuts spec, in the order they are emitted.
+const XDG_MODIFIERS: &[(Modifiers, &str)] = &[
+    (Modifiers::CONTROL, "CTRL"),
+    (Modifiers::ALT, "ALT"),
+    (Modifiers::SHIFT, "SHIFT"),
+    (Modifiers::SUPER, "LOGO"),
+];
+
+/// Key names desktops show in trigger descriptions that aren't keysym names.
+const KEY_ALIASES: &[(&str, &str)] = &[
+    ("Esc", "Escape"),
+    ("Enter", "Return"),
+    ("Del", "Delete"),
+    ("Ins", "Insert"),
+    ("PgUp", "Prior"),
+    ("PgDown", "Next"),
+    ("PageUp", "Prior"),
+    ("PageDown", "Next"),
+    ("Media Play", "XF86AudioPlay"),
+    ("Media Pause", "XF86AudioPause"),
+    ("Media Stop", "XF86AudioStop"),
+    ("Media Next", "XF86AudioNext"),
+    ("Media Previous", "XF86AudioPrev"),
+    ("Volume Up", "XF86AudioRaiseVolume"),
+    ("Volume Down", "XF86AudioLowerVolume"),
+    ("Volume Mute", "XF86AudioMute"),
+];
+
+/// Convert a DeadBeef keystroke like `Ctrl Alt Left` into the trigger syntax
+/// of the XDG shortcuts spec, `CTRL+ALT+Left`.
+///
+/// Returns `None` if the keystroke doesn't parse, the key has no keysym name
+/// or a modifier has no name in the spec, like Hyper and AltGr.
+pub fn to_xdg_trigger(keystroke: &str) -> Option<String> {
+    // An upper case letter is the key with Shift, as the rest of the plugin
+    // and the portal see it.
+    let keystroke = keystroke.parse::<Keystroke>().ok()?.normalized();
+    let name = name_from_keysym(keystroke.keysym)?.to_str().ok()?;
+    let named = XDG_MODIFIERS
+        .iter()
+        .fold(Modifiers::empty(), |all, (m, _)| all | *m);
+    if !(keystroke.modifiers & !named).is_empty() {
+        return None;
+    }
+
+    let mut trigger: Vec<&str> = XDG_MODIFIERS
+        .iter()
+        .filter(|(m, _)| keystroke.modifiers.contains(*m))
+        .map(|(_, name)| *name)
+        .collect();
+    trigger.push(name);
+
+    Some(trigger.join("+"))
+}
+
+/// Convert a trigger description reported by the portal back into a DeadBeef
+/// keystroke.
+///
+/// Descriptions are meant for display and differ between desktops, so this
+/// accepts the XDG syntax (`CTRL+ALT+Left`), the KDE style (`Meta+Shift+P`,
+/// `Media Play`) and GTK accelerators (`<Control><Alt>p`).
+pub fn to_keystroke(description: &str) -> Option<String> {
+    let mut rest = description.trim();
+    let mut modifiers = Modifiers::empty();
+
+    while let Some(tail) = rest.strip_prefix('<') {
+        let (name, tail) = tail.split_once('>')?;
+        modifiers |= modifier_from_str(name)?;
+        rest = tail;
+    }
+
+    let mut tokens: Vec<&str> = rest.split('+').map(str::trim).collect();
+    // `Ctrl++` binds the plus key itself
+    let key = match tokens.pop()? {
+        "" if tokens.last() == Some(&"") => {
+            tokens.pop();
+            "plus"
+        }
+        key => key,
+    };
+    for token in tokens {
+        modifiers |= modifier_from_str(token)?;
+    }
+
+    let keysym = keysym_from_str(&key_name(key)?)?;
+    Some(Keystroke::new(keysym, modifiers).to_string())
+}
+
+fn modifier_from_str(name: &str) -> Option<Modifiers> {
+    match name.to_ascii_lowercase().as_str() {
+        "ctrl" | "control" | "primary" => Some(Modifiers::CONTROL),
+        "alt" | "mod1" => Some(Modifiers::ALT),
+        "shift" => Some(Modifiers::SHIFT),
+        "super" | "logo" | "meta" | "win" => Some(Modifiers::SUPER),
+        "hyper" | "mod3" => Some(Modifiers::HYPER),
+        "altgr" | "mod5" | "iso_level3_shift" => Some(Modifiers::ALT_GR),
+        _ => None,
+    }
+}
+
+fn key_name(name: &str) -> Option<String> {
+    // Desktops show letter keys in upper case, DeadBeef binds the lower case keysym
+    if name.len() == 1 && name.as_bytes()[0].is_ascii_alphabetic() {
+        return Some(name.to_ascii_lowercase());
+    }
+    if keysym_from_str(name).is_some() {
+        return Some(name.to_owned());
+    }
+    if let Some((_, alias)) = KEY_ALIASES
+        .iter()
+        .find(|(a, _)| a.eq_ignore_ascii_case(name))
+    {
+        return Some(alias.to_string());
+    }
+    keysym_name_ignore_case(name).map(str::to_owned)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{to_keystroke, to_xdg_trigger, Keystroke};
+
+    #[test]
+    fn converts_keystrokes() {
+        let table = [
+            ("a", "a"),
+            ("A", "SHIFT+a"),
+            ("1", "1"),
+            ("space", "space"),
+            ("Ctrl k", "CTRL+k"),
+            ("Alt k", "ALT+k"),
+            ("Shift k", "SHIFT+k"),
+            ("Super k", "LOGO+k"),
+            ("Ctrl Alt Left", "CTRL+ALT+Left"),
+            ("Super Shift Alt Ctrl Delete", "CTRL+ALT+SHIFT+LOGO+Delete"),
+            ("ctrl ALT Return", "CTRL+ALT+Return"),
+            ("Ctrl  Tab", "CTRL+Tab"),
+            ("Escape", "Escape"),
+            ("BackSpace", "BackSpace"),
+            ("F1", "F1"),
+            ("Shift F12", "SHIFT+F12"),
+            ("Home", "Home"),
+            ("End", "End"),
+            ("Insert", "Insert"),
+            ("KP_Enter", "KP_Enter"),
+            ("Ctrl KP_Add", "CTRL+KP_Add"),
+            ("Prior", "Prior"),
+            ("Page_Up", "Prior"),
+            ("Page_Down", "Next"),
+            ("XF86AudioPlay", "XF86AudioPlay"),
+            ("XF86AudioNext", "XF86AudioNext"),
+            ("Shift XF86AudioRaiseVolume", "SHIFT+XF86AudioRaiseVolume"),
+            ("0x1008FF14", "XF86AudioPlay"),
+            ("Ctrl 0xff51", "CTRL+Left"),
+            ("0x61", "a"),
+        ];
+
+        for (keystroke, trigger) in table {
+            assert_eq!(
+                to_xdg_trigger(keystroke).as_deref(),
+                Some(trigger),
+                "keystroke {keystroke:?}"
+            );
+        }
+    }
+
+    #[test]
+    fn upper_case_keys_trigger_with_shift() {
+        assert_eq!(to_xdg_trigger("Ctrl P").as_deref(), Some("CTRL+SHIFT+p"));
+
+        // The trigger comes back as the same binding
+        let trigger = to_xdg_trigger("Ctrl A").unwrap();
+        let keystroke: Keystroke = to_keystroke(&trigger).unwrap().parse().unwrap();
+        assert_eq!(
+            keystroke.normalized(),
+            "Ctrl A".parse::<Keystroke>().unwrap().normalized()
+        );
+    }
+
+    #[test]
+    fn rejects_invalid_keystrokes() {
+        for keystroke in [
+            "",
+            "Ctrl",
+            "Ctrl Alt",
+            "NoSuchKey",
+            "Ctrl NoSuchKey",
+            "0xzz",
+            "0x7ffffff0",
+            "Hyper k",
+            "AltGr e",
+        ] {
+            assert_eq!(to_xdg_trigger(keystroke), None, "keystroke {keystroke:?}");
+        }
+    }
+
+    #[test]
+    fn parses_trigger_descriptions() {
+        let table = [
+            ("CTRL+ALT+Left", "Ctrl Alt Left"),
+            ("LOGO+a", "Super a"),
+            ("SHIFT+CTRL+ALT+LOGO+Delete", "Shift Ctrl Super Alt Delete"),
+            ("Ctrl+Shift+P", "Shift Ctrl p"),
+            ("Meta+K", "Super k"),
+            ("Alt+F4", "Alt F4"),
+            ("Ctrl+Esc", "Ctrl Escape"),
+            ("Ctrl+PgUp", "Ctrl Prior"),
+            ("Media Play", "XF86AudioPlay"),
+            ("Shift+Volume Up", "Shift XF86AudioRaiseVolume"),
+            ("XF86AudioNext", "XF86AudioNext"),
+            ("Ctrl+Backspace", "Ctrl BackSpace"),
+            ("Ctrl + Space", "Ctrl space"),
+            ("Ctrl++", "Ctrl plus"),
+            ("<Control><Alt>p", "Ctrl Alt p"),
+            ("<Super>Return", "Super Return"),
+            ("<Primary><Shift>Home", "Shift Ctrl Home"),
+            ("Hyper+K", "Hyper k"),
+            ("AltGr+E", "AltGr e"),
+            ("<Hyper><Mod5>x", "Hyper AltGr x"),
+        ];
+
+        for (description, keystroke) in table {
+            assert_eq!(
+                to_keystroke(description).as_deref(),
+                Some(keystroke),
+                "description {description:?}"
+            );
+        }
+    }
+
+    #[test]
+    fn rejects_unknown_trigger_descriptions() {
+        for description in [
+            "",
+            "Ctrl+",
+            "Hyperdrive+a",
+            "Ctrl+NoSuchKey",
+            "<Control>",
+            "<Foo>a",
+        ] {
+            assert_eq!(
+                to_keystroke(description),
+                None,
+                "description {description:?}"
+            );
+        }
+    }
+
+    #[test]
+    fn trigger_round_trips() {
+        for keystroke in [
+            "Ctrl Alt Left",
+            "Shift Ctrl p",
+            "Super XF86AudioPlay",
+            "Alt F4",
+        ] {
+            let trigger = to_xdg_trigger(keystroke).unwrap();
+            assert_eq!(to_keystroke(&trigger).as_deref(), Some(keystroke));
+        }
+    }
+}
@@ -1,4 +1,4 @@
-use deadbeef_sys::ddb_action_context_t;
+use deadbeef_sys::{ddb_action_context_t, db_log_layer_t, DeadBeef, DDB_LOG_LAYER_INFO};
 
 
 /// Parse lines like: `"Ctrl k" 0 0 toggle_stop_after_album`
@@ -45,6 +45,88 @@ pub fn parse_line(line: &str) -> Result<(String, bool, String, ddb_action_contex
     Ok((keystroke.to_string(), is_global, action_name, ctx))
 }
 
+/// Inverse of [`parse_line`], builds a `hotkey.*` config value.
+pub fn format_line(
+    keystroke: &str,
+    is_global: bool,
+    action_name: &str,
+    ctx: ddb_action_context_t,
+) -> String {
+    format!("\"{keystroke}\" {ctx} {} {action_name}", is_global as i32)
+}
+
+/// Stable 64-bit FNV-1a hash of some lines, as hex.
+///
+/// Used for values stored in the config, so unlike `DefaultHasher` it must not
+/// change between builds.
+pub fn hash_lines<S: AsRef<str>>(lines: &[S]) -> String {
+    let mut hash: u64 = 0xcbf29ce484222325;
+    for line in lines {
+        for b in line.as_ref().bytes().chain(std::iter::once(b'\n')) {
+            hash ^= b as u64;
+            hash = hash.wrapping_mul(0x100000001b3);
+        }
+    }
+    format!("{hash:016x}")
+}
+
+/// Logs a message for the user to DeadBeef's log, which shows the default
+/// layer in its log window, and to `tracing` as a warning or for the info
+/// layer, for debugging.
+pub fn log_to_deadbeef(layer: db_log_layer_t, msg: &str) {
+    if layer == DDB_LOG_LAYER_INFO {
+        tracing::debug!("{msg}");
+    } else {
+        tracing::warn!("{msg}");
+    }
+    DeadBeef::log_detailed(layer, &format!("hotkeys: {msg}\n"));
+}
+
+/// Escapes the slashes in a part of an action title, which would otherwise
+/// nest it in a submenu.
+pub fn escape_title(s: &str) -> String {
+    s.replace('/', "\\/")
+}
+
+/// Escapes a parameter for an action name as `%XX` UTF-8 bytes, so the name
+/// stays a single token without `;`, `,` or quotes: `Late radio` is
+/// `Late%20radio`.
+pub fn percent_encode(s: &str) -> String {
+    let mut out = String::with_capacity(s.len());
+    for c in s.chars() {
+        if c.is_whitespace() || c.is_control() || matches!(c, '%' | ';' | ',' | '"') {
+            let mut buf = [0; 4];
+            for b in c.encode_utf8(&mut buf).bytes() {
+                out.push_str(&format!("%{b:02X}"));
+            }
+        } else {
+            out.push(c);
+        }
+    }
+    out
+}
+
+/// Inverse of [`percent_encode`].
+pub fn percent_decode(s: &str) -> Result<String, String> {
+    let mut bytes = Vec::with_capacity(s.len());
+    let mut rest = s.as_bytes();
+    while let Some((&b, tail)) = rest.split_first() {
+        if b != b'%' {
+            bytes.push(b);
+            rest = tail;
+            continue;
+        }
+        let byte = tail
+            .get(..2)
+            .and_then(|hex| std::str::from_utf8(hex).ok())
+            .and_then(|hex| u8::from_str_radix(hex, 16).ok())
+            .ok_or_else(|| format!("invalid escape in \"{s}\""))?;
+        bytes.push(byte);
+        rest = &tail[2..];
+    }
+    String::from_utf8(bytes).map_err(|_| format!("invalid UTF-8 in \"{s}\""))
+}
+
 pub fn last_segment_after_unescaped_slash(s: &str) -> &str {
     let ci: Vec<(usize, char)> = s.char_indices().collect();
     // walk backward over the char-index pairs
@@ -72,6 +154,7 @@ pub fn last_segment_after_unescaped_slash(s: &str) -> &str {
 
 
 #[cfg(test)]
+#[allow(clippy::bool_assert_comparison)]
 mod tests {
     use super::parse_line;
 
@@ -99,6 +182,25 @@ mod tests {
         assert!(parse_line(line).is_err());
     }
 
+    use super::format_line;
+
+    #[test]
+    fn format_line_round_trips() {
+        let line = "\"Ctrl Alt Left\" 3 1 seek_5p_back";
+        let (keystroke, is_global, action, ctx) = parse_line(line).expect("parse failed");
+        assert_eq!(format_line(&keystroke, is_global, &action, ctx), line);
+    }
+
+    use super::hash_lines;
+
+    #[test]
+    fn hash_lines_is_stable() {
+        assert_eq!(hash_lines::<&str>(&[]), "cbf29ce484222325");
+        assert_eq!(hash_lines(&["a"]), hash_lines(&["a".to_string()]));
+        assert_ne!(hash_lines(&["ab", "c"]), hash_lines(&["a", "bc"]));
+        assert_ne!(hash_lines(&["a", "b"]), hash_lines(&["b", "a"]));
+    }
+
     use super::last_segment_after_unescaped_slash;
 
     #[test]
@@ -146,4 +248,20 @@ mod tests {
     fn escaped_slash_at_start() {
         assert_eq!(last_segment_after_unescaped_slash("\\/abc/def"), "def");
     }
+
+    use super::{percent_decode, percent_encode};
+
+    #[test]
+    fn percent_escapes_round_trip() {
+        assert_eq!(percent_encode("Late radio"), "Late%20radio");
+        assert_eq!(percent_encode("a;b \"c\" 50%"), "a%3Bb%20%22c%22%2050%25");
+        assert_eq!(percent_encode("a,b"), "a%2Cb");
+        for s in ["Late radio", "ä\tø", "100%", "plain"] {
+            assert_eq!(percent_decode(&percent_encode(s)).as_deref(), Ok(s));
+        }
+        assert_eq!(percent_decode("%C3%A4").as_deref(), Ok("ä"));
+        for s in ["%", "%2", "%zz", "%C3"] {
+            assert!(percent_decode(s).is_err(), "{s:?}");
+        }
+    }
 }
@@ -0,0 +1,502 @@
+use crate::{
+    backend::{Backend, Dispatch, KeyEvent, Kind},
+    keystroke::{Keystroke, Modifiers},
+};
+use std::{
+    collections::HashMap,
+    error::Error,
+    sync::{Arc, Mutex},
+    thread,
+    time::Instant,
+};
+use x11rb::{
+    connection::Connection,
+    errors::ConnectionError,
+    protocol::{
+        xproto::{
+            ClientMessageEvent, ConnectionExt, CreateWindowAux, EventMask, GetKeyboardMappingReply,
+            Grab, GrabMode, GrabStatus, Keycode, ModMask, Window, WindowClass,
+        },
+        Event,
+    },
+    rust_connection::RustConnection,
+    CURRENT_TIME,
+};
+
+type X11Result<T> = Result<T, Box<dyn Error + Send + Sync>>;
+
+/// Modifiers that don't take part in matching. NumLock is Mod2 on about every
+/// keyboard layout.
+const IGNORED_MODIFIERS: u16 = Modifiers::LOCKS.bits() as u16;
+const SHIFT: u16 = Modifiers::SHIFT.bits() as u16;
+const LOCK: u16 = Modifiers::LOCK.bits() as u16;
+const NUM_LOCK: u16 = Modifiers::NUM_LOCK.bits() as u16;
+/// Shift, Lock, Control and Mod1 to Mod5, leaving out the mouse button bits.
+const MODIFIER_BITS: u16 = 0xff;
+
+/// Messages to the event loop's wake-up window.
+const MESSAGE_STOP: u32 = 0;
+const MESSAGE_SEQUENCE_TIMEOUT: u32 = 1;
+const MESSAGE_REGRAB: u32 = 2;
+
+/// The server's keycode to keysym table.
+struct Keymap {
+    min_keycode: Keycode,
+    mapping: GetKeyboardMappingReply,
+}
+
+impl Keymap {
+    fn keycodes_for_keysym(&self, keysym: u32) -> Vec<(Keycode, bool)> {
+        keycodes_for_keysym(
+            self.mapping.keysyms_per_keycode,
+            &self.mapping.keysyms,
+            self.min_keycode,
+            keysym,
+        )
+    }
+
+    fn keystroke(&self, keycode: Keycode, mods: u16) -> Option<Keystroke> {
+        keystroke_for_keycode(
+            self.mapping.keysyms_per_keycode,
+            &self.mapping.keysyms,
+            self.min_keycode,
+            keycode,
+            mods,
+        )
+    }
+}
+
+/// Global hotkeys through `XGrabKey` on the root window, for X11 sessions
+/// without the GlobalShortcuts portal.
+pub struct X11Backend {
+    conn: Arc<RustConnection>,
+    /// Unmapped window that only receives the message that stops the event loop.
+    window: Window,
+    /// Keystrokes to grab on the next [`MESSAGE_REGRAB`].
+    keys: Arc<Mutex<Vec<Keystroke>>>,
+    handle: thread::JoinHandle<()>,
+}
+
+impl X11Backend {
+    pub fn new(keys: &[Keystroke], dispatch: Dispatch) -> X11Result<Self> {
+        let (conn, screen) = x11rb::connect(None)?;
+        let root = conn.setup().roots[screen].root;
+
+        let (min_keycode, max_keycode) = (conn.setup().min_keycode, conn.setup().max_keycode);
+        let keymap = Keymap {
+            min_keycode,
+            mapping: conn
+                .get_keyboard_mapping(min_keycode, max_keycode - min_keycode + 1)?
+                .reply()?,
+        };
+        let grabs = grab_keys(&conn, root, &keymap, keys)?;
+
+        let window = conn.generate_id()?;
+        conn.create_window(
+            x11rb::COPY_DEPTH_FROM_PARENT,
+            window,
+            root,
+            0,
+            0,
+            1,
+            1,
+            0,
+            WindowClass::INPUT_ONLY,
+            x11rb::COPY_FROM_PARENT,
+            &CreateWindowAux::new(),
+        )?;
+        conn.flush()?;
+
+        let conn = Arc::new(conn);
+        let keys = Arc::new(Mutex::new(keys.to_vec()));
+        let (thread_conn, thread_keys) = (conn.clone(), keys.clone());
+        let handle = thread::spawn(move || {
+            let result = event_loop(
+                &thread_conn,
+                root,
+                window,
+                &keymap,
+                grabs,
+                &thread_keys,
+                &dispatch,
+            );
+            if let Err(e) = result {
+                tracing::error!("X11 event loop failed: {e}");
+            }
+        });
+
+        Ok(Self {
+            conn,
+            window,
+            keys,
+            handle,
+        })
+    }
+}
+
+impl Backend for X11Backend {
+    fn kind(&self) -> Kind {
+        Kind::X11
+    }
+
+    fn set_keys(&self, keys: &[Keystroke]) {
+        if let Ok(mut k) = self.keys.lock() {
+            *k = keys.to_vec();
+        }
+        if let Err(e) = send_message(&self.conn, self.window, MESSAGE_REGRAB) {
+            tracing::debug!("Unable to regrab X11 keys: {e}");
+        }
+    }
+
+    fn stop(self: Box<Self>) {
+        match send_message(&self.conn, self.window, MESSAGE_STOP) {
+            Ok(()) => {
+                if self.handle.join().is_err() {
+                    tracing::error!("X11 event loop panicked");
+                }
+            }
+            // The connection is gone, so is the event loop.
+            Err(e) => tracing::debug!("Unable to stop X11 event loop: {e}"),
+        }
+    }
+}
+
+fn send_message(
+    conn: &RustConnection,
+    window: Window,
+    message: u32,
+) -> Result<(), ConnectionError> {
+    let event = ClientMessageEvent::new(32, window, x11rb::NONE, [message, 0, 0, 0, 0]);
+    conn.send_event(false, window, EventMask::NO_EVENT, event)?;
+    conn.flush()
+}
+
+/// Grabs every keystroke, returning what each grab maps back to.
+fn grab_keys(
+    conn: &RustConnection,
+    root: Window,
+    keymap: &Keymap,
+    keys: &[Keystroke],
+) -> X11Result<HashMap<(Keycode, u16), Keystroke>> {
+    let mut grabs = HashMap::new();
+    for &keystroke in keys {
+        let keysym = keystroke.keysym;
+        let found = keymap.keycodes_for_keysym(keysym as u32);
+        if found.is_empty() {
+            tracing::warn!("No X11 keycode for keysym {keysym:#x}");
+        }
+
+        for (keycode, shifted) in found {
+            let mut mods = keystroke.modifiers.bits() as u16 & MODIFIER_BITS;
+            if shifted {
+                mods |= SHIFT;
+            }
+
+            for variant in lock_variants(mods) {
+                let grab = conn.grab_key(
+                    false,
+                    root,
+                    ModMask::from(variant),
+                    keycode,
+                    GrabMode::ASYNC,
+                    GrabMode::ASYNC,
+                )?;
+                if let Err(e) = grab.check() {
+                    tracing::warn!("Unable to grab keysym {keysym:#x}, mods {variant:#x}: {e}");
+                }
+            }
+            grabs.insert((keycode, mods), keystroke);
+        }
+    }
+
+    Ok(grabs)
+}
+
+fn event_loop(
+    conn: &Arc<RustConnection>,
+    root: Window,
+    window: Window,
+    keymap: &Keymap,
+    mut grabs: HashMap<(Keycode, u16), Keystroke>,
+    keys: &Mutex<Vec<Keystroke>>,
+    dispatch: &Dispatch,
+) -> X11Result<()> {
+    // Deadline of the key sequence the whole keyboard is grabbed for.
+    let mut sequence: Option<Instant> = None;
+    // Keys whose press was dispatched, the release must match even when the
+    // modifiers were let go first.
+    let mut down: HashMap<Keycode, Keystroke> = HashMap::new();
+    let mut next = None;
+
+    loop {
+        let event = match next.take() {
+            Some(event) => event,
+            None => conn.wait_for_event()?,
+        };
+        match event {
+            Event::KeyPress(event) => {
+                let mods = clean_state(u16::from(event.state));
+                let keystroke = match sequence {
+                    Some(_) => keymap.keystroke(event.detail, mods),
+                    None => grabs.get(&(event.detail, mods)).copied(),
+                };
+                if let Some(keystroke) = keystroke {
+                    down.insert(event.detail, keystroke);
+                    let deadline = dispatch(keystroke, KeyEvent::Press);
+                    sequence = follow_sequence(conn, root, window, sequence, deadline)?;
+                }
+            }
+            Event::KeyRelease(event) => {
+                // Key repeat shows up as a release and a press with the same
+                // timestamp, both queued by the time the release is read.
+                next = conn.poll_for_event()?;
+                if let Some(Event::KeyPress(press)) = &next {
+                    if press.detail == event.detail && press.time == event.time {
+                        next = None;
+                        continue;
+                    }
+                }
+
+                if let Some(keystroke) = down.remove(&event.detail) {
+                    let deadline = dispatch(keystroke, KeyEvent::Release);
+                    sequence = follow_sequence(conn, root, window, sequence, deadline)?;
+                }
+            }
+            Event::ClientMessage(event) if event.window == window => {
+                match event.data.as_data32()[0] {
+                    MESSAGE_SEQUENCE_TIMEOUT => {
+                        // Stale timers find a later deadline.
+                        if sequence.is_some_and(|d| Instant::now() >= d) {
+                            sequence = follow_sequence(conn, root, window, sequence, None)?;
+                        }
+                    }
+                    MESSAGE_REGRAB => {
+                        conn.ungrab_key(Grab::ANY, root, ModMask::ANY)?;
+                        let keys = keys.lock().map(|k| k.clone()).unwrap_or_default();
+                        grabs = grab_keys(conn, root, keymap, &keys)?;
+                        conn.flush()?;
+                    }
+                    _ => break,
+                }
+            }
+            _ => {}
+        }
+    }
+
+    conn.destroy_window(window)?;
+    conn.flush()?;
+    Ok(())
+}
+
+/// Grabs the whole keyboard while a key sequence is pending, since its next
+/// keystroke may not be grabbed on its own, and releases it afterwards.
+fn follow_sequence(
+    conn: &Arc<RustConnection>,
+    root: Window,
+    window: Window,
+    current: Option<Instant>,
+    deadline: Option<Instant>,
+) -> X11Result<Option<Instant>> {
+    let Some(deadline) = deadline else {
+        if current.is_some() {
+            conn.ungrab_keyboard(CURRENT_TIME)?;
+            conn.flush()?;
+        }
+        return Ok(None);
+    };
+
+    if current.is_none() {
+        let status = conn
+            .grab_keyboard(false, root, CURRENT_TIME, GrabMode::ASYNC, GrabMode::ASYNC)?
+            .reply()?
+            .status;
+        if status != GrabStatus::SUCCESS {
+            tracing::debug!("Unable to grab the keyboard for a key sequence: {status:?}");
+            return Ok(None);
+        }
+    }
+
+    if current != Some(deadline) {
+        let conn = conn.clone();
+        thread::spawn(move || {
+            thread::sleep(deadline.saturating_duration_since(Instant::now()));
+            // Fails once the backend is gone, which is fine.
+            let _ = send_message(&conn, window, MESSAGE_SEQUENCE_TIMEOUT);
+        });
+    }
+    Ok(Some(deadline))
+}
+
+/// Keycodes producing a keysym, and whether Shift is needed to get it.
+fn keycodes_for_keysym(
+    keysyms_per_keycode: u8,
+    keysyms: &[u32],
+    min_keycode: Keycode,
+    keysym: u32,
+) -> Vec<(Keycode, bool)> {
+    let per_keycode = (keysyms_per_keycode as usize).max(1);
+    let unshifted: Vec<_> = keysyms
+        .chunks(per_keycode)
+        .enumerate()
+        .filter(|(_, syms)| syms.first() == Some(&keysym))
+        .map(|(i, _)| (min_keycode + i as u8, false))
+        .collect();
+    if !unshifted.is_empty() {
+        return unshifted;
+    }
+
+    keysyms
+        .chunks(per_keycode)
+        .enumerate()
+        .filter(|(_, syms)| syms.get(1) == Some(&keysym))
+        .map(|(i, _)| (min_keycode + i as u8, true))
+        .collect()
+}
+
+/// The keystroke a key press makes while the keyboard is grabbed for a key
+/// sequence.
+///
+/// Like the grabs, a key that gives another symbol with Shift is reported as
+/// that symbol without Shift, `exclam` rather than `Shift 1`, except for
+/// letters, which are reported as `Shift a`.
+fn keystroke_for_keycode(
+    keysyms_per_keycode: u8,
+    keysyms: &[u32],
+    min_keycode: Keycode,
+    keycode: Keycode,
+    mods: u16,
+) -> Option<Keystroke> {
+    let per_keycode = (keysyms_per_keycode as usize).max(1);
+    let index = keycode.checked_sub(min_keycode)? as usize;
+    let syms = keysyms.get(index * per_keycode..(index + 1) * per_keycode)?;
+    let base = *syms.first().filter(|&&s| s != 0)?;
+
+    if mods & SHIFT != 0 {
+        let is_upper_case = |s: u32| (0x61..=0x7a).contains(&base) && s == base - 0x20;
+        if let Some(&shifted) = syms
+            .get(1)
+            .filter(|&&s| s != 0 && s != base && !is_upper_case(s))
+        {
+            return Some(Keystroke::from_raw(shifted as i32, (mods & !SHIFT) as i32));
+        }
+    }
+    Some(Keystroke::from_raw(base as i32, mods as i32))
+}
+
+/// Every combination of the ignored modifiers on top of `mods`, since a grab
+/// only matches the exact modifier state.
+fn lock_variants(mods: u16) -> [u16; 4] {
+    [mods, mods | LOCK, mods | NUM_LOCK, mods | LOCK | NUM_LOCK]
+}
+
+fn clean_state(state: u16) -> u16 {
+    state & MODIFIER_BITS & !IGNORED_MODIFIERS
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const CTRL: u16 = Modifiers::CONTROL.bits() as u16;
+
+    #[test]
+    fn lock_variants_cover_capslock_and_numlock() {
+        assert_eq!(lock_variants(CTRL), [0x04, 0x06, 0x14, 0x16]);
+        assert!(lock_variants(CTRL)
+            .into_iter()
+            .all(|v| clean_state(v) == CTRL));
+    }
+
+    #[test]
+    fn clean_state_drops_locks_and_buttons() {
+        let ctrl_alt = CTRL | Modifiers::ALT.bits() as u16;
+        assert_eq!(clean_state(ctrl_alt | 0x02 | 0x10 | 0x100), ctrl_alt);
+        assert_eq!(clean_state(SHIFT), SHIFT);
+        // Mod3 and Mod5, Hyper and AltGr, stay
+        assert_eq!(clean_state(0x20 | 0x80 | NUM_LOCK), 0xa0);
+    }
+
+    #[test]
+    fn keycodes_for_keysym_prefers_unshifted_column() {
+        // keycode 8: a A, keycode 9: 1 exclam, keycode 10: a (again, e.g. a second layout)
+        let keysyms = [0x61, 0x41, 0x31, 0x21, 0x61, 0x41];
+        assert_eq!(
+            keycodes_for_keysym(2, &keysyms, 8, 0x61),
+            [(8, false), (10, false)]
+        );
+        assert_eq!(keycodes_for_keysym(2, &keysyms, 8, 0x21), [(9, true)]);
+        assert_eq!(keycodes_for_keysym(2, &keysyms, 8, 0xff51), []);
+    }
+
+    #[test]
+    fn keystroke_for_keycode_follows_grab_convention() {
+        // keycode 8: a A, keycode 9: 1 exclam, keycode 10: Escape
+        let keysyms = [0x61, 0x41, 0x31, 0x21, 0xff1b, 0];
+        let key = Keystroke::new;
+
+        assert_eq!(
+            keystroke_for_keycode(2, &keysyms, 8, 8, 0),
+            Some(key(0x61, Modifiers::empty()))
+        );
+        assert_eq!(
+            keystroke_for_keycode(2, &keysyms, 8, 8, SHIFT | CTRL),
+            Some(key(0x61, Modifiers::SHIFT | Modifiers::CONTROL))
+        );
+        assert_eq!(
+            keystroke_for_keycode(2, &keysyms, 8, 9, SHIFT),
+            Some(key(0x21, Modifiers::empty()))
+        );
+        assert_eq!(
+            keystroke_for_keycode(2, &keysyms, 8, 10, SHIFT),
+            Some(key(0xff1b, Modifiers::SHIFT))
+        );
+        assert_eq!(keystroke_for_keycode(2, &keysyms, 8, 7, 0), None);
+        assert_eq!(keystroke_for_keycode(2, &keysyms, 8, 11, 0), None);
+    }
+
+    /// Run with `xvfb-run cargo test -- --ignored`.
+    #[test]
+    #[ignore = "needs an X server"]
+    fn grabs_and_dispatches_keystroke() {
+        use std::{sync::mpsc, time::Duration};
+        use x11rb::protocol::xtest::ConnectionExt as _;
+
+        let ctrl_a = Keystroke::new(0x61, Modifiers::CONTROL);
+        let (tx, rx) = mpsc::channel();
+        let tx = std::sync::Mutex::new(tx);
+        let dispatch: Dispatch = Arc::new(move |keystroke, key_event| {
+            if key_event == KeyEvent::Press {
+                tx.lock().unwrap().send(keystroke).unwrap();
+            }
+            None
+        });
+        let backend = X11Backend::new(&[ctrl_a], dispatch).expect("X11 backend");
+
+        let (conn, screen) = x11rb::connect(None).unwrap();
+        let root = conn.setup().roots[screen].root;
+        let (min_keycode, max_keycode) = (conn.setup().min_keycode, conn.setup().max_keycode);
+        let mapping = conn
+            .get_keyboard_mapping(min_keycode, max_keycode - min_keycode + 1)
+            .unwrap()
+            .reply()
+            .unwrap();
+        let keycode = |keysym| {
+            keycodes_for_keysym(
+                mapping.keysyms_per_keycode,
+                &mapping.keysyms,
+                min_keycode,
+                keysym,
+            )[0]
+            .0
+        };
+        let (control, a) = (keycode(0xffe3), keycode(0x61));
+
+        for (type_, key) in [(2, control), (2, a), (3, a), (3, control)] {
+            conn.xtest_fake_input(type_, key, 0, root, 0, 0, 0).unwrap();
+        }
+        conn.flush().unwrap();
+
+        assert_eq!(rx.recv_timeout(Duration::from_secs(5)), Ok(ctrl_a));
+        Box::new(backend).stop();
+    }
+}